@@ -0,0 +1,10 @@
+//! `no_std` build of the program's `math` module for off-chain clients (e.g. compiled
+//! to WASM). The module is included from the program's own source file, so its
+//! results match the on-chain logic exactly; building this crate (and running the
+//! module's tests here) checks that it stays free of `std` and Solana dependencies.
+#![no_std]
+
+#[path = "../../../programs/mcar-vesting/src/math.rs"]
+mod math;
+
+pub use math::*;
@@ -0,0 +1,935 @@
+//! Core read computations shared by the instruction handlers.
+//!
+//! These functions take plain account structs rather than an Anchor `Context`, so
+//! other programs can depend on this crate (with the `cpi` feature) and compute
+//! unlocks, yield and reflections exactly as the program does, without a CPI.
+
+use anchor_lang::prelude::*;
+
+use crate::{
+    math, GlobalConfig, ProgramError, SolvencyReport, UserStake, BPS_DENOMINATOR, REFLECTION_INDEX_SCALE,
+    SECONDS_IN_DAY,
+};
+
+pub use crate::math::{unlock_fraction, unlock_fraction_at};
+
+/// Cumulative amount the 7-day schedule has released this stake epoch, before
+/// subtracting withdrawals. Never below `stake.unlocked_floor`.
+pub fn scheduled_unlock(stake: &UserStake, config: &GlobalConfig, current_timestamp: i64) -> Result<u64> {
+    math::scheduled_unlock(
+        stake.staked_amount,
+        stake.total_withdrawn,
+        stake.unlocked_floor,
+        stake.bonus_unlock_bps,
+        stake.start_timestamp,
+        config.unlock_curve,
+        current_timestamp,
+    )
+    .ok_or(ProgramError::CalculationOverflow.into())
+}
+
+/// Amount currently available for withdrawal under the 7-day unlock schedule.
+pub fn unlocked_amount(stake: &UserStake, config: &GlobalConfig, current_timestamp: i64) -> Result<u64> {
+    math::unlocked_amount(
+        stake.staked_amount,
+        stake.total_withdrawn,
+        stake.unlocked_floor,
+        stake.bonus_unlock_bps,
+        stake.start_timestamp,
+        config.unlock_curve,
+        current_timestamp,
+    )
+    .ok_or(ProgramError::CalculationOverflow.into())
+}
+
+/// Yield accrued since `stake.last_yield_claim_time`, clamped by the per-user cap.
+/// Nothing accrues during the last yield pause, from `yield_paused_at` until it resumes.
+pub fn accrued_yield(
+    stake: &UserStake,
+    config: &GlobalConfig,
+    current_timestamp: i64,
+) -> Result<u64> {
+    // Nothing accrues before emissions start
+    let accrual_start = config.yield_accrual_start(stake.last_yield_claim_time);
+    let paused = config.yield_paused_seconds(accrual_start, current_timestamp);
+    let yield_amount = math::accrued_yield(
+        stake.yield_bearing_amount(),
+        config.yield_rate_bps,
+        accrual_start,
+        current_timestamp.saturating_sub(paused),
+    )
+    .ok_or(ProgramError::CalculationOverflow)?;
+
+    if config.max_unclaimed_yield_per_user > 0 {
+        let headroom = config
+            .max_unclaimed_yield_per_user
+            .saturating_sub(stake.unclaimed_yield);
+        return Ok(yield_amount.min(headroom));
+    }
+
+    Ok(yield_amount)
+}
+
+/// Bonus yield accrued since `stake.last_bonus_claim_time`, on the same principal and
+/// formula as the primary yield but at `bonus_rate_bps`. Nothing accrues before the
+/// bonus was enabled.
+pub fn accrued_bonus_yield(stake: &UserStake, config: &GlobalConfig, current_timestamp: i64) -> Result<u64> {
+    if config.bonus_emissions_start == 0 {
+        return Ok(0);
+    }
+    math::accrued_yield(
+        stake.yield_bearing_amount(),
+        config.bonus_rate_bps,
+        stake.last_bonus_claim_time.max(config.bonus_emissions_start),
+        current_timestamp,
+    )
+    .ok_or(ProgramError::CalculationOverflow.into())
+}
+
+/// Seconds from `current_timestamp` until a claim would pay at least one raw yield
+/// unit: 0 when it already would, -1 when nothing can accrue (no rate, no yield-bearing
+/// principal, the per-user cap reached, or yield paused).
+pub fn seconds_to_next_yield_unit(
+    stake: &UserStake,
+    config: &GlobalConfig,
+    current_timestamp: i64,
+) -> Result<i64> {
+    if pending_yield(stake, config, current_timestamp)? > 0 {
+        return Ok(0);
+    }
+    let principal = stake.yield_bearing_amount();
+    let capped = config.max_unclaimed_yield_per_user > 0
+        && stake.unclaimed_yield >= config.max_unclaimed_yield_per_user;
+    if principal == 0 || config.yield_rate_bps == 0 || capped || config.yield_paused {
+        return Ok(-1);
+    }
+
+    // Smallest t with principal * rate * t / (10_000 * SECONDS_IN_YEAR) >= 1
+    let seconds_needed = (BPS_DENOMINATOR as u128 * math::SECONDS_IN_YEAR as u128)
+        .div_ceil(principal as u128 * config.yield_rate_bps as u128);
+    let seconds_needed = i64::try_from(seconds_needed).map_err(|_| ProgramError::CalculationOverflow)?;
+    let accrual_start = config.yield_accrual_start(stake.last_yield_claim_time);
+    let ready_at = accrual_start
+        .saturating_add(seconds_needed)
+        .saturating_add(config.yield_paused_seconds(accrual_start, current_timestamp));
+    Ok(ready_at.saturating_sub(current_timestamp).max(0))
+}
+
+/// Total yield a claim would pay now: banked `unclaimed_yield` plus fresh accrual.
+pub fn pending_yield(
+    stake: &UserStake,
+    config: &GlobalConfig,
+    current_timestamp: i64,
+) -> Result<u64> {
+    stake
+        .unclaimed_yield
+        .checked_add(accrued_yield(stake, config, current_timestamp)?)
+        .ok_or(ProgramError::CalculationOverflow.into())
+}
+
+/// Loyalty-weighted stake at `current_timestamp`:
+/// `staked_amount * (1 + days_held * bonus / 10_000)`, capped at the max multiplier.
+pub fn loyalty_weight(
+    stake: &UserStake,
+    config: &GlobalConfig,
+    current_timestamp: i64,
+) -> Result<u64> {
+    if stake.start_timestamp == 0 || config.loyalty_bonus_bps_per_day == 0 {
+        return Ok(stake.staked_amount);
+    }
+
+    let days_held = current_timestamp
+        .saturating_sub(stake.start_timestamp)
+        .max(0)
+        / SECONDS_IN_DAY;
+    let multiplier_bps = (days_held as u64)
+        .saturating_mul(config.loyalty_bonus_bps_per_day as u64)
+        .saturating_add(BPS_DENOMINATOR)
+        .min(config.max_loyalty_multiplier_bps as u64)
+        .max(BPS_DENOMINATOR);
+
+    let weight = (stake.staked_amount as u128)
+        .checked_mul(multiplier_bps as u128)
+        .and_then(|x| x.checked_div(BPS_DENOMINATOR as u128))
+        .ok_or(ProgramError::CalculationOverflow)?;
+    u64::try_from(weight).map_err(|_| ProgramError::CalculationOverflow.into())
+}
+
+/// Balance reflections are paid on: the weight recorded at the user's last
+/// interaction while the loyalty bonus is on, otherwise the staked principal.
+pub fn reflection_basis(stake: &UserStake, config: &GlobalConfig) -> u64 {
+    if config.loyalty_bonus_bps_per_day == 0 || stake.reflection_weight == 0 {
+        stake.staked_amount
+    } else {
+        stake.reflection_weight
+    }
+}
+
+/// The stake's share of `config.total_staked` in basis points, floored (0 when nothing is staked).
+pub fn pool_share_bps(stake: &UserStake, config: &GlobalConfig) -> Result<u16> {
+    if config.total_staked == 0 {
+        return Ok(0);
+    }
+    let share = mul_div(stake.staked_amount, BPS_DENOMINATOR, config.total_staked)?;
+    Ok(share.min(BPS_DENOMINATOR) as u16)
+}
+
+/// Reflection lamports a claim would pay now: settled `unclaimed_reflections` plus
+/// fresh accrual.
+pub fn pending_reflections(stake: &UserStake, config: &GlobalConfig) -> Result<u64> {
+    stake
+        .unclaimed_reflections
+        .checked_add(accrued_reflections(stake, config)?)
+        .ok_or(ProgramError::CalculationOverflow.into())
+}
+
+/// Reflections accrued on the current balance since `last_claimed_index`
+/// (bounded by the per-claim index cap).
+pub fn accrued_reflections(stake: &UserStake, config: &GlobalConfig) -> Result<u64> {
+    accrued_reflections_until(stake, config, stake.claim_target_index(config))
+}
+
+/// Reflections accrued on the current balance between `last_claimed_index` and `target_index`.
+pub fn accrued_reflections_until(
+    stake: &UserStake,
+    config: &GlobalConfig,
+    target_index: u128,
+) -> Result<u64> {
+    if stake.staked_amount == 0 {
+        return Ok(0);
+    }
+    math::reflection_delta(reflection_basis(stake, config), stake.last_claimed_index, target_index)
+        .ok_or(ProgramError::CalculationOverflow.into())
+}
+
+/// Days of yield the reward vault can pay at the current stake and rate. The daily
+/// liability is rounded up so the runway errs short; banked `unclaimed_yield` is
+/// not tracked globally and so is not included.
+pub fn solvency_report(config: &GlobalConfig, reward_vault_balance: u64) -> Result<SolvencyReport> {
+    const DAYS_IN_YEAR: u128 = 365;
+    let yearly_scaled = (config.total_staked as u128)
+        .checked_mul(config.yield_rate_bps as u128)
+        .ok_or(ProgramError::CalculationOverflow)?;
+    let daily_liability =
+        u64::try_from(yearly_scaled.div_ceil(BPS_DENOMINATOR as u128 * DAYS_IN_YEAR))
+            .map_err(|_| ProgramError::CalculationOverflow)?;
+
+    // Nothing accrues: unlimited runway
+    let days_of_runway = reward_vault_balance
+        .checked_div(daily_liability)
+        .unwrap_or(u64::MAX);
+
+    Ok(SolvencyReport {
+        days_of_runway,
+        reward_vault_balance,
+        daily_liability,
+    })
+}
+
+/// Splits a reflection deposit into `(sink_lamports, distributed_lamports)`. Without
+/// a configured sink everything is distributed.
+pub fn split_reflection_deposit(config: &GlobalConfig, sol_amount: u64) -> Result<(u64, u64)> {
+    if config.reflection_sink.is_none() {
+        return Ok((0, sol_amount));
+    }
+    let sink_lamports = (sol_amount as u128)
+        .checked_mul(config.reflection_sink_bps as u128)
+        .and_then(|x| x.checked_div(BPS_DENOMINATOR as u128))
+        .ok_or(ProgramError::CalculationOverflow)?;
+    let sink_lamports =
+        u64::try_from(sink_lamports).map_err(|_| ProgramError::CalculationOverflow)?;
+    Ok((sink_lamports, sol_amount - sink_lamports))
+}
+
+/// Splits a yield claim into `(user_amount, earmarked_amount)`: `yield_to_reflection_bps`
+/// of it goes to the yield reflection buffer for conversion into reflections.
+pub fn split_yield_claim(config: &GlobalConfig, amount: u64) -> Result<(u64, u64)> {
+    let earmarked = mul_div(amount, config.yield_to_reflection_bps as u64, BPS_DENOMINATOR)?;
+    Ok((amount - earmarked, earmarked))
+}
+
+/// Splits a reflection claim into `(paid_lamports, reinvested_lamports)` by the position's
+/// `reinvest_ratio_bps`. The reinvested share rounds down, in favour of the payout.
+pub fn split_reinvest(lamports: u64, reinvest_ratio_bps: u16) -> Result<(u64, u64)> {
+    let reinvested = mul_div(lamports, reinvest_ratio_bps as u64, BPS_DENOMINATOR)?;
+    Ok((lamports - reinvested, reinvested))
+}
+
+/// Stake tokens an immediate-eligibility stake of `amount` pays to the yield reflection
+/// buffer, rounded down in the staker's favour.
+pub fn immediate_eligibility_fee(config: &GlobalConfig, amount: u64) -> Result<u64> {
+    mul_div(amount, config.immediate_eligibility_fee_bps as u64, BPS_DENOMINATOR)
+}
+
+/// Amount a stake of `amount` credits after the mint's transfer `fee`, rejecting the
+/// stake with `SlippageExceeded` when that falls below the caller's `min_credited`.
+pub fn net_stake_credit(amount: u64, fee: u64, min_credited: u64) -> Result<u64> {
+    let credited = amount
+        .checked_sub(fee)
+        .ok_or(ProgramError::CalculationOverflow)?;
+    require!(credited >= min_credited, ProgramError::SlippageExceeded);
+    Ok(credited)
+}
+
+/// Share of a reflection deposit diverted to the burn treasury for off-chain buyback,
+/// taken from the full `sol_amount` like the sink share.
+pub fn reflection_burn_lamports(config: &GlobalConfig, sol_amount: u64) -> Result<u64> {
+    mul_div(sol_amount, config.reflection_burn_bps as u64, BPS_DENOMINATOR)
+}
+
+/// Lamports stakers can claim from an index increase of `index_increase`, using the
+/// loyalty-weighted total when it exceeds the staked principal.
+pub fn reflection_liability(config: &GlobalConfig, index_increase: u128) -> Result<u64> {
+    let liability = index_increase
+        .checked_mul(config.staked_reflection_basis() as u128)
+        .and_then(|x| x.checked_div(REFLECTION_INDEX_SCALE))
+        .ok_or(ProgramError::CalculationOverflow)?;
+    u64::try_from(liability).map_err(|_| ProgramError::CalculationOverflow.into())
+}
+
+/// `amount * numerator / denominator` (floored), staying in `u64` when the product
+/// fits and only widening to `u128` for very large amounts (see `math::mul_div`).
+pub fn mul_div(amount: u64, numerator: u64, denominator: u64) -> Result<u64> {
+    math::mul_div(amount, numerator, denominator).ok_or(ProgramError::CalculationOverflow.into())
+}
+
+/// Index increase produced by depositing `sol_amount` lamports over `total_supply` tokens.
+pub fn reflection_index_increase(sol_amount: u64, total_supply: u64) -> Result<u128> {
+    scaled_index_increase(sol_amount, total_supply, REFLECTION_INDEX_SCALE)
+}
+
+/// `current_index` with the contribution of a past deposit of `deposit_amount` lamports
+/// recomputed over `new_supply` instead of the wrong `old_supply` it was indexed with.
+/// Returns `(corrected_index, wrong_increase, correct_increase)`.
+pub fn rebased_reflection_index(
+    current_index: u128,
+    old_supply: u64,
+    new_supply: u64,
+    deposit_amount: u64,
+) -> Result<(u128, u128, u128)> {
+    require!(
+        old_supply > 0 && new_supply > 0 && deposit_amount > 0,
+        ProgramError::InvalidIndexRebase
+    );
+    let wrong_increase = reflection_index_increase(deposit_amount, old_supply)?;
+    let correct_increase = reflection_index_increase(deposit_amount, new_supply)?;
+    // The wrong contribution must actually be part of the index
+    let corrected = current_index
+        .checked_sub(wrong_increase)
+        .ok_or(ProgramError::InvalidIndexRebase)?
+        .checked_add(correct_increase)
+        .ok_or(ProgramError::CalculationOverflow)?;
+    Ok((corrected, wrong_increase, correct_increase))
+}
+
+/// Smallest deposit whose index increase over `total_supply` is at least `index_delta`
+/// (the inverse of `reflection_index_increase`, rounded up so the target is reached).
+pub fn payout_for_index_delta(index_delta: u128, total_supply: u64) -> Result<u64> {
+    let lamports = index_delta
+        .checked_mul(total_supply as u128)
+        .ok_or(ProgramError::CalculationOverflow)?
+        .div_ceil(REFLECTION_INDEX_SCALE);
+    u64::try_from(lamports).map_err(|_| ProgramError::CalculationOverflow.into())
+}
+
+/// Annual yield in bps when the simple rate `rate_bps` is compounded `compounds_per_year`
+/// times: `(1 + rate / n)^n - 1`, floored. 0 compounds means no compounding (the simple
+/// rate).
+///
+/// Precision: fixed-point with 18 decimals, raised to the n-th power by repeated
+/// squaring. Every intermediate product is floored, so the result never overstates
+/// the APY; the relative error before the final floor is below `n * 1e-18`, far under
+/// one bp even for per-second compounding. The result is exact to within 1 bp below
+/// the true value. Only rates far above `MAX_YIELD_BPS` overflow.
+pub fn effective_apy(rate_bps: u16, compounds_per_year: u64) -> Result<u64> {
+    const WAD: u128 = 1_000_000_000_000_000_000;
+    if compounds_per_year == 0 {
+        return Ok(rate_bps as u64);
+    }
+    let mul = |a: u128, b: u128| -> Result<u128> {
+        Ok(a.checked_mul(b).ok_or(ProgramError::CalculationOverflow)? / WAD)
+    };
+
+    let mut factor = WAD + rate_bps as u128 * WAD / (BPS_DENOMINATOR as u128 * compounds_per_year as u128);
+    let mut growth = WAD;
+    let mut periods = compounds_per_year;
+    while periods > 0 {
+        if periods & 1 == 1 {
+            growth = mul(growth, factor)?;
+        }
+        periods >>= 1;
+        if periods > 0 {
+            factor = mul(factor, factor)?;
+        }
+    }
+    let apy_bps = (growth - WAD) * BPS_DENOMINATOR as u128 / WAD;
+    u64::try_from(apy_bps).map_err(|_| ProgramError::CalculationOverflow.into())
+}
+
+/// `reflection_index_increase` for an arbitrary index scale. A deposit whose scaled
+/// amount doesn't fit in `u128` fails with `ReflectionDepositTooLarge` (split it into
+/// smaller deposits) rather than a generic overflow.
+fn scaled_index_increase(sol_amount: u64, total_supply: u64, scale: u128) -> Result<u128> {
+    let scaled = (sol_amount as u128)
+        .checked_mul(scale)
+        .ok_or(ProgramError::ReflectionDepositTooLarge)?;
+    scaled
+        .checked_div(total_supply as u128)
+        .ok_or(ProgramError::CalculationOverflow.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{UNLOCK_CURVE_CONTINUOUS, UNLOCK_CURVE_EXPONENTIAL, UNLOCK_CURVE_LINEAR, UNLOCK_PERIOD_DAYS};
+
+    const SECONDS_IN_YEAR: i64 = 365 * 24 * 60 * 60;
+
+    fn stake(staked_amount: u64, start_timestamp: i64) -> UserStake {
+        UserStake {
+            staked_amount,
+            start_timestamp,
+            last_yield_claim_time: start_timestamp,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn unlocked_amount_follows_schedule() {
+        let start = 1_700_000_000;
+        let config = GlobalConfig::default();
+        let s = stake(1_000, start);
+        assert_eq!(unlocked_amount(&s, &config, start - 1).unwrap(), 0);
+        assert_eq!(unlocked_amount(&s, &config, start + 4 * SECONDS_IN_DAY).unwrap(), 400);
+        assert_eq!(
+            unlocked_amount(&s, &config, start + UNLOCK_PERIOD_DAYS * SECONDS_IN_DAY).unwrap(),
+            1_000
+        );
+        assert_eq!(unlocked_amount(&UserStake::default(), &config, start).unwrap(), 0);
+    }
+
+    #[test]
+    fn extreme_timestamps_never_panic() {
+        let extremes = [i64::MIN, i64::MIN + 1, -SECONDS_IN_DAY, -1, 0, 1, SECONDS_IN_DAY, i64::MAX - 1, i64::MAX];
+        // Extremes plus a deterministic pseudo-random spread (64-bit LCG)
+        let mut seed: u64 = 0x9E37_79B9_7F4A_7C15;
+        let mut timestamps: Vec<i64> = extremes.to_vec();
+        for _ in 0..500 {
+            seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+            timestamps.push(seed as i64);
+        }
+
+        for curve in [UNLOCK_CURVE_LINEAR, UNLOCK_CURVE_EXPONENTIAL, UNLOCK_CURVE_CONTINUOUS] {
+            let config = GlobalConfig {
+                unlock_curve: curve,
+                ..Default::default()
+            };
+            for &start in timestamps.iter().filter(|&&t| t != 0) {
+                for &now in &timestamps {
+                    let s = stake(u64::MAX / 3, start);
+                    let unlocked = unlocked_amount(&s, &config, now).unwrap();
+                    assert!(unlocked <= s.staked_amount, "start {start}, now {now}");
+                    if now <= start {
+                        assert_eq!(unlocked, 0, "start {start}, now {now}");
+                    }
+                }
+            }
+        }
+
+        // A corrupted, hugely negative start counts as fully vested instead of wrapping to 0
+        let s = stake(1_000, i64::MIN);
+        assert_eq!(unlocked_amount(&s, &GlobalConfig::default(), i64::MAX).unwrap(), 1_000);
+    }
+
+    #[test]
+    fn continuous_curve_releases_every_second() {
+        let start = 1_700_000_000;
+        let stepped = GlobalConfig::default();
+        let continuous = GlobalConfig {
+            unlock_curve: UNLOCK_CURVE_CONTINUOUS,
+            ..Default::default()
+        };
+        let s = stake(1_000_000, start);
+
+        // 3.5 days in: the stepped curve is at 3 full days, the continuous one halfway
+        let half_week = start + 7 * SECONDS_IN_DAY / 2;
+        assert_eq!(unlocked_amount(&s, &stepped, half_week).unwrap(), 300_000);
+        assert_eq!(unlocked_amount(&s, &continuous, half_week).unwrap(), 500_000);
+
+        // Grows within a day, unlike the stepped curve
+        let hour = start + 3_600;
+        assert_eq!(unlocked_amount(&s, &stepped, hour).unwrap(), 0);
+        assert_eq!(unlocked_amount(&s, &continuous, hour).unwrap(), 1_000_000 * 3_600 / (7 * SECONDS_IN_DAY) as u64);
+
+        // Both fully unlock after 7 days
+        let end = start + UNLOCK_PERIOD_DAYS * SECONDS_IN_DAY;
+        assert_eq!(unlocked_amount(&s, &continuous, end - 1).unwrap(), 999_998);
+        assert_eq!(unlocked_amount(&s, &continuous, end).unwrap(), 1_000_000);
+        assert_eq!(unlocked_amount(&s, &stepped, end).unwrap(), 1_000_000);
+    }
+
+    #[test]
+    fn exponential_curve_is_back_loaded() {
+        let start = 1_700_000_000;
+        let linear = GlobalConfig::default();
+        let exponential = GlobalConfig {
+            unlock_curve: UNLOCK_CURVE_EXPONENTIAL,
+            ..Default::default()
+        };
+        let s = stake(12_700, start);
+
+        // floor(12_700 * (2^d - 1) / 127) = 100 * (2^d - 1)
+        let expected_exponential = [0, 100, 300, 700, 1_500, 3_100, 6_300, 12_700, 12_700];
+        let expected_linear = [0, 1_270, 2_540, 3_810, 5_080, 6_350, 7_620, 12_700, 12_700];
+        for day in 0..=8 {
+            let now = start + day * SECONDS_IN_DAY;
+            let exp = unlocked_amount(&s, &exponential, now).unwrap();
+            let lin = unlocked_amount(&s, &linear, now).unwrap();
+            assert_eq!(exp, expected_exponential[day as usize], "exponential day {day}");
+            assert_eq!(lin, expected_linear[day as usize], "linear day {day}");
+            // Back-loaded: never ahead of the linear schedule
+            assert!(exp <= lin, "day {day}");
+        }
+
+        // Both reach the full stake at the end, for any amount
+        for amount in [1, 999, u64::MAX / 2] {
+            let s = stake(amount, start);
+            let end = start + UNLOCK_PERIOD_DAYS * SECONDS_IN_DAY;
+            assert_eq!(unlocked_amount(&s, &exponential, end).unwrap(), amount);
+            assert_eq!(unlocked_amount(&s, &linear, end).unwrap(), amount);
+        }
+    }
+
+    #[test]
+    fn pending_yield_includes_banked_yield() {
+        let config = GlobalConfig {
+            yield_rate_bps: 500, // 5% APR
+            ..Default::default()
+        };
+        let mut s = stake(10_000, 0);
+        assert_eq!(accrued_yield(&s, &config, SECONDS_IN_YEAR).unwrap(), 500);
+        s.unclaimed_yield = 25;
+        assert_eq!(pending_yield(&s, &config, SECONDS_IN_YEAR).unwrap(), 525);
+        // Handlers and library agree
+        assert_eq!(
+            s.calculate_yield(&config, SECONDS_IN_YEAR).unwrap(),
+            accrued_yield(&s, &config, SECONDS_IN_YEAR).unwrap()
+        );
+    }
+
+    #[test]
+    fn no_yield_before_emissions_start() {
+        let emissions_start = 1_700_000_000;
+        let config = GlobalConfig {
+            yield_rate_bps: 500,
+            emissions_start_timestamp: emissions_start,
+            ..Default::default()
+        };
+        // Registered (and staked) a year before emissions begin
+        let s = stake(10_000, emissions_start - SECONDS_IN_YEAR);
+        assert_eq!(accrued_yield(&s, &config, emissions_start - 1).unwrap(), 0);
+        assert_eq!(accrued_yield(&s, &config, emissions_start).unwrap(), 0);
+        // Accrual counts from emissions start, not from the earlier claim time
+        assert_eq!(accrued_yield(&s, &config, emissions_start + SECONDS_IN_YEAR).unwrap(), 500);
+        assert_eq!(config.yield_accrual_start(emissions_start - 1), emissions_start);
+        assert_eq!(config.yield_accrual_start(emissions_start + 1), emissions_start + 1);
+    }
+
+    #[test]
+    fn pending_reflections_match_index_math() {
+        let increase = reflection_index_increase(1_000_000, 1_000_000_000).unwrap();
+        let config = GlobalConfig {
+            reflection_index: increase,
+            ..Default::default()
+        };
+        // 0.001 lamports per token over 5_000_000 staked tokens
+        let s = stake(5_000_000, 1);
+        assert_eq!(pending_reflections(&s, &config).unwrap(), 5_000);
+        assert!(reflection_index_increase(1, 0).is_err());
+    }
+
+    #[test]
+    fn oversized_deposit_has_dedicated_error() {
+        // The current scale fits any u64 deposit
+        assert!(reflection_index_increase(u64::MAX, 1).is_ok());
+
+        // A scale 1e12 times larger overflows for a near-u64::MAX deposit
+        let larger_scale = REFLECTION_INDEX_SCALE * REFLECTION_INDEX_SCALE * 1_000_000_000_000;
+        let err = scaled_index_increase(u64::MAX, 1_000_000, larger_scale).unwrap_err();
+        assert_eq!(err, ProgramError::ReflectionDepositTooLarge.into());
+        assert!(scaled_index_increase(100, 1_000_000, larger_scale).is_ok());
+
+        // A zero supply is still a plain calculation error
+        let err = scaled_index_increase(1, 0, REFLECTION_INDEX_SCALE).unwrap_err();
+        assert_eq!(err, ProgramError::CalculationOverflow.into());
+    }
+
+    /// Deterministic 64-bit LCG for the property tests below.
+    fn lcg(seed: &mut u64) -> u64 {
+        *seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+        *seed
+    }
+
+    #[test]
+    fn large_yield_inputs_never_panic() {
+        let overflow: Error = ProgramError::CalculationOverflow.into();
+        let mut seed: u64 = 0x2545_F491_4F6C_DD1D;
+        for _ in 0..2_000 {
+            let staked_amount = lcg(&mut seed);
+            let start = lcg(&mut seed) as i64;
+            let now = lcg(&mut seed) as i64;
+            let rate = lcg(&mut seed) as u16;
+            let config = GlobalConfig {
+                yield_rate_bps: rate,
+                ..Default::default()
+            };
+            let s = stake(staked_amount, start);
+
+            let elapsed = now.saturating_sub(start);
+            let expected = if elapsed <= 0 || rate == 0 {
+                Some(0)
+            } else {
+                (staked_amount as u128)
+                    .checked_mul(rate as u128)
+                    .and_then(|x| x.checked_mul(elapsed as u128))
+                    .map(|x| x / 10_000 / SECONDS_IN_YEAR as u128)
+                    .and_then(|x| u64::try_from(x).ok())
+            };
+            match (accrued_yield(&s, &config, now), expected) {
+                (Ok(amount), Some(expected)) => assert_eq!(amount, expected),
+                (Err(err), None) => assert_eq!(err, overflow),
+                (got, expected) => panic!("stake {staked_amount}, {start}..{now}: {got:?} vs {expected:?}"),
+            }
+        }
+
+        // More than u64::MAX of yield is an error, not a truncated payout
+        let config = GlobalConfig {
+            yield_rate_bps: 10_000,
+            ..Default::default()
+        };
+        let err = accrued_yield(&stake(u64::MAX, 0), &config, 2 * SECONDS_IN_YEAR).unwrap_err();
+        assert_eq!(err, overflow);
+    }
+
+    #[test]
+    fn large_reflection_inputs_never_panic() {
+        let overflow: Error = ProgramError::CalculationOverflow.into();
+        let config = GlobalConfig::default();
+        let mut seed: u64 = 0xD1B5_4A32_D192_ED03;
+        for _ in 0..2_000 {
+            let staked_amount = lcg(&mut seed);
+            let last_claimed_index = ((lcg(&mut seed) as u128) << 64) | lcg(&mut seed) as u128;
+            // Mix small and full-width index differences
+            let index_diff = match lcg(&mut seed) % 3 {
+                0 => (lcg(&mut seed) % 1_000_000) as u128 * REFLECTION_INDEX_SCALE,
+                1 => lcg(&mut seed) as u128,
+                _ => ((lcg(&mut seed) as u128) << 64) | lcg(&mut seed) as u128,
+            };
+            let Some(target) = last_claimed_index.checked_add(index_diff) else {
+                continue;
+            };
+            let s = UserStake {
+                last_claimed_index,
+                ..stake(staked_amount, 1)
+            };
+
+            let expected = if staked_amount == 0 || index_diff == 0 {
+                Some(0)
+            } else {
+                index_diff
+                    .checked_mul(staked_amount as u128)
+                    .map(|x| x / REFLECTION_INDEX_SCALE)
+                    .and_then(|x| u64::try_from(x).ok())
+            };
+            match (accrued_reflections_until(&s, &config, target), expected) {
+                (Ok(amount), Some(expected)) => assert_eq!(amount, expected),
+                (Err(err), None) => assert_eq!(err, overflow),
+                (got, expected) => panic!("stake {staked_amount}, diff {index_diff}: {got:?} vs {expected:?}"),
+            }
+        }
+
+        // A result past u64::MAX is an error, not a truncated payout
+        let s = stake(u64::MAX, 1);
+        let err = accrued_reflections_until(&s, &config, 2 * REFLECTION_INDEX_SCALE).unwrap_err();
+        assert_eq!(err, overflow);
+    }
+
+    #[test]
+    fn pool_shares_sum_to_about_10000_bps() {
+        let stakes = [stake(1_000, 1), stake(2_000, 1), stake(4_000, 1)];
+        let config = GlobalConfig {
+            total_staked: stakes.iter().map(|s| s.staked_amount).sum(),
+            ..Default::default()
+        };
+        let shares: Vec<u16> = stakes.iter().map(|s| pool_share_bps(s, &config).unwrap()).collect();
+        assert_eq!(shares, vec![1_428, 2_857, 5_714]);
+        // Flooring loses at most 1 bps per staker
+        let total: u64 = shares.iter().map(|&b| b as u64).sum();
+        assert!(total <= 10_000 && total >= 10_000 - stakes.len() as u64);
+
+        let sole = GlobalConfig {
+            total_staked: 10_000,
+            ..Default::default()
+        };
+        assert_eq!(pool_share_bps(&stake(10_000, 1), &sole).unwrap(), 10_000);
+        // Nothing staked anywhere
+        assert_eq!(pool_share_bps(&stake(0, 0), &GlobalConfig::default()).unwrap(), 0);
+    }
+
+    #[test]
+    fn yield_claim_earmarks_its_reflection_share() {
+        let mut config = GlobalConfig::default();
+        assert_eq!(split_yield_claim(&config, 1_001).unwrap(), (1_001, 0));
+
+        config.yield_to_reflection_bps = 2_000; // 20%
+        assert_eq!(split_yield_claim(&config, 1_000).unwrap(), (800, 200));
+        // The earmark rounds down, in the user's favour
+        assert_eq!(split_yield_claim(&config, 1_001).unwrap(), (801, 200));
+
+        config.yield_to_reflection_bps = 10_000;
+        assert_eq!(split_yield_claim(&config, u64::MAX).unwrap(), (0, u64::MAX));
+    }
+
+    #[test]
+    fn immediate_eligibility_fee_from_bps() {
+        let mut config = GlobalConfig::default();
+        assert_eq!(immediate_eligibility_fee(&config, 1_000).unwrap(), 0);
+        config.immediate_eligibility_fee_bps = 150; // 1.5%
+        assert_eq!(immediate_eligibility_fee(&config, 1_000).unwrap(), 15);
+        assert_eq!(immediate_eligibility_fee(&config, 99).unwrap(), 1);
+    }
+
+    #[test]
+    fn reinvest_ratio_splits_reflection_claims() {
+        assert_eq!(split_reinvest(1_001, 0).unwrap(), (1_001, 0));
+        assert_eq!(split_reinvest(1_000, 5_000).unwrap(), (500, 500));
+        // The reinvested share rounds down, paying out the odd lamport
+        assert_eq!(split_reinvest(1_001, 5_000).unwrap(), (501, 500));
+        assert_eq!(split_reinvest(u64::MAX, 10_000).unwrap(), (0, u64::MAX));
+    }
+
+    #[test]
+    fn reflection_burn_share_is_not_indexed() {
+        let config = GlobalConfig {
+            reflection_sink: Some(Pubkey::new_unique()),
+            reflection_sink_bps: 2_500,
+            reflection_burn_bps: 1_000,
+            ..Default::default()
+        };
+        let deposit = 1_000_003;
+        let (sink, remainder) = split_reflection_deposit(&config, deposit).unwrap();
+        let burn = reflection_burn_lamports(&config, deposit).unwrap();
+        assert_eq!((sink, burn), (250_000, 100_000));
+        let distributed = remainder - burn;
+        assert_eq!(sink + burn + distributed, deposit);
+        assert_eq!(
+            reflection_index_increase(distributed, 1_000).unwrap(),
+            650_003 * REFLECTION_INDEX_SCALE / 1_000
+        );
+
+        // No burn configured: nothing is diverted
+        assert_eq!(reflection_burn_lamports(&GlobalConfig::default(), deposit).unwrap(), 0);
+    }
+
+    #[test]
+    fn rebase_reconstructs_index_of_a_bad_deposit() {
+        let good = reflection_index_increase(3_000_000, 1_000_000).unwrap();
+        let bad_deposit = 1_000_000;
+        let real_supply = 4_000_000;
+        // The bad deposit was indexed over a supply 4x too small
+        let skewed = good + reflection_index_increase(bad_deposit, real_supply / 4).unwrap();
+        let correct = good + reflection_index_increase(bad_deposit, real_supply).unwrap();
+
+        let (healed, wrong, right) =
+            rebased_reflection_index(skewed, real_supply / 4, real_supply, bad_deposit).unwrap();
+        assert_eq!(healed, correct);
+        assert_eq!(wrong, 4 * right);
+        // An index too small to contain the wrong contribution is rejected
+        assert!(rebased_reflection_index(wrong - 1, real_supply / 4, real_supply, bad_deposit).is_err());
+        assert!(rebased_reflection_index(skewed, 0, real_supply, bad_deposit).is_err());
+    }
+
+    #[test]
+    fn time_to_next_yield_unit_matches_first_accrual() {
+        let start = 1_700_000_000;
+        let config = GlobalConfig {
+            yield_rate_bps: 1_000,
+            ..Default::default()
+        };
+        let stake = UserStake {
+            staked_amount: 7,
+            start_timestamp: start,
+            last_yield_claim_time: start,
+            ..Default::default()
+        };
+        let wait = seconds_to_next_yield_unit(&stake, &config, start + 60).unwrap();
+        assert!(wait > 0);
+        let ready = start + 60 + wait;
+        assert_eq!(accrued_yield(&stake, &config, ready - 1).unwrap(), 0);
+        assert_eq!(accrued_yield(&stake, &config, ready).unwrap(), 1);
+        assert_eq!(seconds_to_next_yield_unit(&stake, &config, ready).unwrap(), 0);
+
+        // Banked yield is already claimable
+        let banked = UserStake { unclaimed_yield: 1, ..stake.clone() };
+        assert_eq!(seconds_to_next_yield_unit(&banked, &config, start).unwrap(), 0);
+        // Nothing ever accrues without a rate or principal
+        let no_rate = GlobalConfig::default();
+        assert_eq!(seconds_to_next_yield_unit(&stake, &no_rate, start).unwrap(), -1);
+        let empty = UserStake { staked_amount: 0, ..stake };
+        assert_eq!(seconds_to_next_yield_unit(&empty, &config, start).unwrap(), -1);
+    }
+
+    #[test]
+    fn stake_credit_guards_against_transfer_fees() {
+        // 1% fee against a 2% tolerance: credited net of the fee
+        assert_eq!(net_stake_credit(10_000, 100, 9_800).unwrap(), 9_900);
+        // No fee: the full amount, and a guard of exactly the amount passes
+        assert_eq!(net_stake_credit(10_000, 0, 10_000).unwrap(), 10_000);
+        // 5% fee against the same tolerance reverts
+        assert_eq!(
+            net_stake_credit(10_000, 500, 9_800).unwrap_err(),
+            ProgramError::SlippageExceeded.into()
+        );
+        assert!(net_stake_credit(100, 101, 0).is_err());
+    }
+
+    #[test]
+    fn reflection_sink_takes_its_share_first() {
+        let mut config = GlobalConfig {
+            reflection_sink_bps: 2_500, // 25%
+            ..Default::default()
+        };
+        // No sink configured: bps is ignored
+        assert_eq!(split_reflection_deposit(&config, 1_000).unwrap(), (0, 1_000));
+
+        config.reflection_sink = Some(Pubkey::new_unique());
+        let (sink, remainder) = split_reflection_deposit(&config, 1_000_003).unwrap();
+        assert_eq!(sink, 250_000);
+        assert_eq!(remainder, 750_003);
+
+        // Index is computed on the remainder only
+        assert_eq!(
+            reflection_index_increase(remainder, 1_000).unwrap(),
+            750_003 * REFLECTION_INDEX_SCALE / 1_000
+        );
+    }
+
+    #[test]
+    fn index_delta_and_payout_round_trip() {
+        // Exact division both ways
+        let delta = reflection_index_increase(1_000, 1_000).unwrap();
+        assert_eq!(delta, REFLECTION_INDEX_SCALE);
+        assert_eq!(payout_for_index_delta(delta, 1_000).unwrap(), 1_000);
+
+        // 1 lamport over 3 tokens floors the index; the inverse rounds back up to 1
+        let delta = reflection_index_increase(1, 3).unwrap();
+        assert_eq!(delta, REFLECTION_INDEX_SCALE / 3);
+        assert_eq!(payout_for_index_delta(delta, 3).unwrap(), 1);
+        // One index unit more than 1 lamport can buy needs a second lamport
+        assert_eq!(payout_for_index_delta(delta + 1, 3).unwrap(), 2);
+
+        // The payout always reaches the requested delta, never overshooting by a lamport
+        for (target, supply) in [(1u128, 7u64), (999_999, 1_000_003), (REFLECTION_INDEX_SCALE + 1, 10)] {
+            let lamports = payout_for_index_delta(target, supply).unwrap();
+            assert!(reflection_index_increase(lamports, supply).unwrap() >= target);
+            assert!(reflection_index_increase(lamports - 1, supply).unwrap() < target);
+        }
+
+        assert_eq!(payout_for_index_delta(0, 1_000).unwrap(), 0);
+        assert!(payout_for_index_delta(u128::MAX, 2).is_err());
+    }
+
+    #[test]
+    fn reflection_liability_covers_stakers_share() {
+        let mut config = GlobalConfig {
+            total_staked: 400,
+            ..Default::default()
+        };
+        // 1_000 lamports over 1_000 tokens, 400 of them staked
+        let increase = reflection_index_increase(1_000, 1_000).unwrap();
+        assert_eq!(reflection_liability(&config, increase).unwrap(), 400);
+
+        // Loyalty weight counts when the bonus is on
+        config.total_reflection_weight = 500;
+        assert_eq!(reflection_liability(&config, increase).unwrap(), 400);
+        config.loyalty_bonus_bps_per_day = 1;
+        assert_eq!(reflection_liability(&config, increase).unwrap(), 500);
+    }
+
+    #[test]
+    fn solvency_runway_from_known_inputs() {
+        let mut config = GlobalConfig {
+            total_staked: 3_650_000,
+            yield_rate_bps: 1_000, // 10% APR -> 365_000 a year, 1_000 a day
+            ..Default::default()
+        };
+        let report = solvency_report(&config, 45_500).unwrap();
+        assert_eq!(report.daily_liability, 1_000);
+        assert_eq!(report.days_of_runway, 45);
+        assert_eq!(report.reward_vault_balance, 45_500);
+
+        // Fractional liabilities round up
+        config.total_staked = 3_650_001;
+        assert_eq!(solvency_report(&config, 45_500).unwrap().daily_liability, 1_001);
+
+        // Nothing accrues: unlimited runway
+        config.yield_rate_bps = 0;
+        assert_eq!(solvency_report(&config, 0).unwrap().days_of_runway, u64::MAX);
+    }
+
+    #[test]
+    fn long_term_staker_earns_larger_reflection_share() {
+        let now = 1_700_000_000 + 100 * SECONDS_IN_DAY;
+        let mut config = GlobalConfig {
+            loyalty_bonus_bps_per_day: 100, // +1% per day
+            max_loyalty_multiplier_bps: 15_000, // capped at 1.5x
+            ..Default::default()
+        };
+        let mut veteran = stake(1_000, now - 30 * SECONDS_IN_DAY);
+        let mut newcomer = stake(1_000, now - SECONDS_IN_DAY / 2);
+        let mut ancient = stake(1_000, now - 90 * SECONDS_IN_DAY);
+
+        assert_eq!(loyalty_weight(&veteran, &config, now).unwrap(), 1_300);
+        assert_eq!(loyalty_weight(&newcomer, &config, now).unwrap(), 1_000);
+        assert_eq!(loyalty_weight(&ancient, &config, now).unwrap(), 1_500); // Cap
+
+        for s in [&mut veteran, &mut newcomer, &mut ancient] {
+            crate::refresh_reflection_weight(&mut config, s, now).unwrap();
+        }
+        config.total_staked = 3_000;
+        assert_eq!(config.total_reflection_weight, 3_800);
+
+        // 3_800 lamports over a 3_000 token supply: the bonus weight is in the denominator
+        let denominator = config.reflection_denominator(3_000).unwrap();
+        assert_eq!(denominator, 3_800);
+        config.reflection_index = reflection_index_increase(3_800, denominator).unwrap();
+        let veteran_share = pending_reflections(&veteran, &config).unwrap();
+        let newcomer_share = pending_reflections(&newcomer, &config).unwrap();
+        assert_eq!(veteran_share, 1_300);
+        assert_eq!(newcomer_share, 1_000);
+        assert!(veteran_share > newcomer_share);
+
+        // Bonus off: everyone is paid on principal again
+        config.loyalty_bonus_bps_per_day = 0;
+        assert_eq!(pending_reflections(&veteran, &config).unwrap(), 1_000);
+        assert_eq!(config.reflection_denominator(3_000).unwrap(), 3_000);
+    }
+
+    #[test]
+    fn test_effective_apy_vs_simple_apr() {
+        // Compounding once a year (or not at all) is the simple APR
+        assert_eq!(effective_apy(1_000, 0).unwrap(), 1_000);
+        assert_eq!(effective_apy(1_000, 1).unwrap(), 1_000);
+        // 10% APR: (1 + 0.1/12)^12 - 1 = 10.47%, (1 + 0.1/365)^365 - 1 = 10.515%
+        assert_eq!(effective_apy(1_000, 12).unwrap(), 1_047);
+        assert_eq!(effective_apy(1_000, 365).unwrap(), 1_051);
+        // Per-second compounding approaches e^0.1 - 1 = 10.517%
+        assert_eq!(effective_apy(1_000, SECONDS_IN_YEAR as u64).unwrap(), 1_051);
+        assert_eq!(effective_apy(500, 365).unwrap(), 512);
+        // 100% APR compounded daily: 171.46%
+        assert_eq!(effective_apy(10_000, 365).unwrap(), 17_145);
+        assert_eq!(effective_apy(0, 365).unwrap(), 0);
+        // The highest configurable rate still fits the fixed-point range
+        assert_eq!(effective_apy(crate::MAX_YIELD_BPS, 365).unwrap(), 63_488);
+    }
+}
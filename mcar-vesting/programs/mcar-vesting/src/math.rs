@@ -0,0 +1,248 @@
+//! Unlock, yield and reflection math on primitives.
+//!
+//! This module depends on nothing but `core`: no Anchor, Solana or `std` imports, so
+//! it also compiles under `no_std` (see the `mcar-vesting-math` crate, which builds
+//! this same file for WASM clients) and clients get exactly the on-chain results.
+//! `calc` wraps these functions for the account types. Overflow returns `None`;
+//! `calc` maps it to `CalculationOverflow`.
+
+pub const SECONDS_IN_DAY: i64 = 86400; // As per PRD
+pub const SECONDS_IN_YEAR: i64 = 365 * SECONDS_IN_DAY; // Simple APR year, no leap days
+// Full days after start_timestamp until the whole stake is unlocked (10% per day, capped)
+pub const UNLOCK_PERIOD_DAYS: i64 = 7;
+
+// Unlock curves selectable via GlobalConfig::unlock_curve (see unlock_fraction_at)
+pub const UNLOCK_CURVE_LINEAR: u8 = 0; // Stepped: 10% per full day
+pub const UNLOCK_CURVE_EXPONENTIAL: u8 = 1;
+pub const UNLOCK_CURVE_CONTINUOUS: u8 = 2; // Proportional to seconds elapsed
+
+// Basis points in 1x (rates, loyalty multipliers)
+pub const BPS_DENOMINATOR: u64 = 10_000;
+
+// Scaling factor for reflection index (1e12)
+pub const REFLECTION_INDEX_SCALE: u128 = 1_000_000_000_000;
+
+/// Fraction of the cycle basis released after `days_elapsed` full days, as
+/// `(numerator, denominator)`, for the day-stepped curves. Both release everything
+/// after `UNLOCK_PERIOD_DAYS`.
+///
+/// - `UNLOCK_CURVE_LINEAR` (the default): `10 * d / 100` for `d < 7` (10% per day).
+/// - `UNLOCK_CURVE_EXPONENTIAL`: `(2^d - 1) / (2^7 - 1)` for `d < 7`, i.e. the
+///   released amount doubles (plus one share) every day and most of the stake unlocks
+///   near the end: 0.8%, 2.4%, 5.5%, 11.8%, 24.4%, 49.6%, then 100% on day 7.
+///
+/// The amount is `floor(basis * numerator / denominator)`.
+pub fn unlock_fraction(curve: u8, days_elapsed: i64) -> (u64, u64) {
+    if days_elapsed >= UNLOCK_PERIOD_DAYS {
+        return (1, 1);
+    }
+    let days = days_elapsed.max(0) as u32;
+    match curve {
+        UNLOCK_CURVE_EXPONENTIAL => ((1u64 << days) - 1, (1u64 << UNLOCK_PERIOD_DAYS) - 1),
+        _ => (days as u64 * 10, 100),
+    }
+}
+
+/// Fraction of the cycle basis released `seconds_elapsed` after the cycle start, as
+/// `(numerator, denominator)`. `UNLOCK_CURVE_CONTINUOUS` releases proportionally every
+/// second, `seconds / (7 * SECONDS_IN_DAY)`; the other curves step once per full day
+/// (see `unlock_fraction`).
+pub fn unlock_fraction_at(curve: u8, seconds_elapsed: i64) -> (u64, u64) {
+    let period = UNLOCK_PERIOD_DAYS * SECONDS_IN_DAY;
+    let seconds_elapsed = seconds_elapsed.clamp(0, period);
+    if curve == UNLOCK_CURVE_CONTINUOUS {
+        return (seconds_elapsed as u64, period as u64);
+    }
+    unlock_fraction(curve, seconds_elapsed / SECONDS_IN_DAY)
+}
+
+/// `floor(amount * numerator / denominator)`, in `u64` when the product fits and in
+/// `u128` otherwise. `None` when the result overflows `u64` or the denominator is 0.
+pub fn mul_div(amount: u64, numerator: u64, denominator: u64) -> Option<u64> {
+    match amount.checked_mul(numerator) {
+        Some(product) => product.checked_div(denominator),
+        None => mul_div_wide(amount, numerator, denominator),
+    }
+}
+
+/// `u128` fallback for `mul_div`.
+pub fn mul_div_wide(amount: u64, numerator: u64, denominator: u64) -> Option<u64> {
+    let scaled = (amount as u128)
+        .checked_mul(numerator as u128)?
+        .checked_div(denominator as u128)?;
+    u64::try_from(scaled).ok()
+}
+
+/// Cumulative amount the 7-day schedule has released this stake epoch, before
+/// subtracting withdrawals. Never below `unlocked_floor`. A `start_timestamp` of 0
+/// means no cycle is running.
+pub fn scheduled_unlock(
+    staked_amount: u64,
+    total_withdrawn: u64,
+    unlocked_floor: u64,
+    bonus_unlock_bps: u16,
+    start_timestamp: i64,
+    curve: u8,
+    current_timestamp: i64,
+) -> Option<u64> {
+    // PRD: "For the 7 days following this start_timestamp, 10% of the *total currently staked amount* becomes available..."
+    // If start_timestamp is 0 (no stake yet or fully unstaked), nothing is available.
+    if start_timestamp == 0 || staked_amount == 0 {
+        return Some(0); // Nothing staked or vesting not started, nothing to unlock
+    }
+
+    // Time passed, clamped to the schedule before any multiplication. Saturating
+    // keeps corrupted timestamps in range: time before start counts as 0, an
+    // overflowing gap as fully vested.
+    let seconds_elapsed = current_timestamp.saturating_sub(start_timestamp);
+
+    // Share released so far under the configured curve (100% after 7 days)
+    let (numerator, denominator) = unlock_fraction_at(curve, seconds_elapsed);
+
+    // The schedule applies to the stake as it was at the start of the cycle, so
+    // partial withdrawals can't re-unlock a percentage of the reduced balance
+    let cycle_basis = staked_amount.checked_add(total_withdrawn)?;
+
+    // Admin-granted bonus unlock is released on top of the schedule
+    let scheduled_unlock = mul_div(cycle_basis, numerator, denominator)?
+        .saturating_add(mul_div(cycle_basis, bonus_unlock_bps as u64, BPS_DENOMINATOR)?);
+
+    // Unlocked amounts only ratchet up within an epoch
+    Some(scheduled_unlock.min(cycle_basis).max(unlocked_floor))
+}
+
+/// Amount currently available for withdrawal under the 7-day unlock schedule
+/// (`scheduled_unlock` minus this cycle's withdrawals, never above the stake).
+pub fn unlocked_amount(
+    staked_amount: u64,
+    total_withdrawn: u64,
+    unlocked_floor: u64,
+    bonus_unlock_bps: u16,
+    start_timestamp: i64,
+    curve: u8,
+    current_timestamp: i64,
+) -> Option<u64> {
+    let scheduled = scheduled_unlock(
+        staked_amount,
+        total_withdrawn,
+        unlocked_floor,
+        bonus_unlock_bps,
+        start_timestamp,
+        curve,
+        current_timestamp,
+    )?;
+    Some(scheduled.saturating_sub(total_withdrawn).min(staked_amount))
+}
+
+/// Simple-APR yield on `principal` at `rate_bps` from `accrual_start` to
+/// `current_timestamp`: `principal * rate * elapsed / (10_000 * SECONDS_IN_YEAR)`,
+/// floored. Nothing accrues while the clock is at or behind the start.
+pub fn accrued_yield(principal: u64, rate_bps: u16, accrual_start: i64, current_timestamp: i64) -> Option<u64> {
+    // Saturates instead of overflowing on extreme timestamps
+    let time_elapsed = current_timestamp.saturating_sub(accrual_start);
+    if principal == 0 || rate_bps == 0 || time_elapsed <= 0 {
+        return Some(0);
+    }
+    let yield_amount = (principal as u128)
+        .checked_mul(rate_bps as u128)?
+        .checked_mul(time_elapsed as u128)?
+        / BPS_DENOMINATOR as u128
+        / SECONDS_IN_YEAR as u128;
+    u64::try_from(yield_amount).ok()
+}
+
+/// Reflections earned by `basis` tokens while the index moved from `last_index` to
+/// `target_index`: `(target - last) * basis / REFLECTION_INDEX_SCALE`, floored.
+/// 0 when the index did not move forward.
+pub fn reflection_delta(basis: u64, last_index: u128, target_index: u128) -> Option<u64> {
+    if basis == 0 || target_index <= last_index {
+        return Some(0);
+    }
+    let reward = (target_index - last_index).checked_mul(basis as u128)? / REFLECTION_INDEX_SCALE;
+    u64::try_from(reward).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mul_div_fast_path_matches_wide_path() {
+        // xorshift64: deterministic inputs without a test-only dependency
+        let mut seed = 0x9E37_79B9_7F4A_7C15u64;
+        let mut next = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        for i in 0..10_000 {
+            // Mix full-range amounts (wide path) with small ones (fast path)
+            let amount = if i % 2 == 0 { next() } else { next() >> (next() % 64) };
+            let percentage = next() % 101;
+            assert_eq!(
+                mul_div(amount, percentage, 100).unwrap(),
+                mul_div_wide(amount, percentage, 100).unwrap(),
+                "amount {amount}, percentage {percentage}"
+            );
+            // Exponential curve fractions: (2^d - 1) / (2^7 - 1)
+            let numerator = (1 << (next() % 8)) - 1;
+            assert_eq!(
+                mul_div(amount, numerator, 127).unwrap(),
+                mul_div_wide(amount, numerator, 127).unwrap(),
+                "amount {amount}, numerator {numerator}"
+            );
+        }
+
+        // Boundary of the fast path
+        let edge = u64::MAX / 100;
+        for amount in [0, 1, edge, edge + 1, u64::MAX] {
+            for percentage in [0, 10, 70, 100] {
+                assert_eq!(
+                    mul_div(amount, percentage, 100).unwrap(),
+                    mul_div_wide(amount, percentage, 100).unwrap()
+                );
+            }
+        }
+        assert_eq!(mul_div(u64::MAX, 100, 100).unwrap(), u64::MAX);
+        assert!(mul_div(1, 1, 0).is_none());
+    }
+
+    #[test]
+    fn unlock_schedule_on_primitives() {
+        let day = SECONDS_IN_DAY;
+        let unlocked = |withdrawn, floor, bonus, curve, now| {
+            unlocked_amount(1_000 - withdrawn, withdrawn, floor, bonus, 1, curve, 1 + now).unwrap()
+        };
+        assert_eq!(unlocked(0, 0, 0, UNLOCK_CURVE_LINEAR, 0), 0);
+        assert_eq!(unlocked(0, 0, 0, UNLOCK_CURVE_LINEAR, 3 * day + 1), 300);
+        assert_eq!(unlocked(0, 0, 0, UNLOCK_CURVE_CONTINUOUS, 7 * day / 2), 500);
+        assert_eq!(unlocked(0, 0, 0, UNLOCK_CURVE_EXPONENTIAL, 3 * day), 55);
+        assert_eq!(unlocked(0, 0, 0, UNLOCK_CURVE_LINEAR, 7 * day), 1_000);
+        // Withdrawals count against the cycle basis, bonus and floor add on top
+        assert_eq!(unlocked(200, 0, 0, UNLOCK_CURVE_LINEAR, 3 * day), 100);
+        assert_eq!(unlocked(0, 0, 1_000, UNLOCK_CURVE_LINEAR, day), 200);
+        assert_eq!(unlocked(0, 400, 0, UNLOCK_CURVE_LINEAR, day), 400);
+        // No running cycle
+        assert_eq!(unlocked_amount(1_000, 0, 0, 0, 0, UNLOCK_CURVE_LINEAR, day), Some(0));
+        assert_eq!(scheduled_unlock(u64::MAX, 1, 0, 0, 1, UNLOCK_CURVE_LINEAR, day), None);
+    }
+
+    #[test]
+    fn yield_and_reflections_on_primitives() {
+        // 10% APR on 1M tokens for a year, half a year, and not at all
+        assert_eq!(accrued_yield(1_000_000, 1_000, 0, SECONDS_IN_YEAR), Some(100_000));
+        assert_eq!(accrued_yield(1_000_000, 1_000, 0, SECONDS_IN_YEAR / 2), Some(50_000));
+        assert_eq!(accrued_yield(1_000_000, 1_000, 100, 100), Some(0));
+        assert_eq!(accrued_yield(1_000_000, 1_000, 100, 50), Some(0));
+        assert_eq!(accrued_yield(0, 1_000, 0, SECONDS_IN_YEAR), Some(0));
+        assert_eq!(accrued_yield(u64::MAX, u16::MAX, i64::MIN, i64::MAX), None);
+
+        // 2.5 lamports per token over 1_000 tokens
+        let scale = REFLECTION_INDEX_SCALE;
+        assert_eq!(reflection_delta(1_000, scale, scale * 7 / 2), Some(2_500));
+        assert_eq!(reflection_delta(1_000, 2 * scale, scale), Some(0));
+        assert_eq!(reflection_delta(0, 0, scale), Some(0));
+        assert_eq!(reflection_delta(u64::MAX, 0, u128::MAX), None);
+    }
+}
@@ -11,11 +11,13 @@ import * as anchor from "@coral-xyz/anchor";
 import { Program, BN } from "@coral-xyz/anchor";
 import { McarVesting, IDL } from "../target/types/mcar_vesting";
 import {
-  Keypair, PublicKey, SystemProgram, LAMPORTS_PER_SOL,
+  Keypair, PublicKey, SystemProgram, LAMPORTS_PER_SOL, Ed25519Program, Transaction,
+  SYSVAR_INSTRUCTIONS_PUBKEY,
 } from "@solana/web3.js";
 import {
   TOKEN_PROGRAM_ID, createMint, getOrCreateAssociatedTokenAccount,
-  mintTo, getAccount, getMint,
+  mintTo, getAccount, getMint, createAccount, approve, revoke, NATIVE_MINT,
+  getAssociatedTokenAddressSync,
 } from "@solana/spl-token";
 import chai, { expect } from "chai";
 import chaiAsPromised from "chai-as-promised";
@@ -110,7 +112,7 @@ describe("MCar Vesting — PRD v1.1 exhaustive", () => {
     await mintTo(connection, admin, mint, holderAta, admin,   300_000n*10n**9n);
 
     // initialise config (5 % APR)
-    await program.methods.initialize(500).accounts({
+    await program.methods.initialize(500, 0).accounts({
       admin: admin.publicKey,
       config: cfgPda,
       vaultAuthority: authPda,
@@ -202,27 +204,28 @@ describe("MCar Vesting — PRD v1.1 exhaustive", () => {
       expect(info.stakedAmount.eq(toBN(STAKE).sub(tenPercent))).to.be.true;
     });
 
-    // day 3 (+ buffer) — can withdraw 30 %
-    it("day 3 (+ buffer) — can withdraw 30 %", async () => {
+    // day 3 (+ buffer) — cumulative 30 %, 10 % already withdrawn on day 1
+    it("day 3 (+ buffer) — can withdraw the remaining 20 %", async () => {
       // Already at day 1+, warp 2 more days + buffer
       await warpSeconds(connection, 2 * DAY_SECONDS + CHUNK_SECONDS / 2); // Warp to day 3 + 3 hours
-      const thirty = toBN(STAKE / 10n * 3n);
-      await program.methods.unstake(thirty).accounts({
+      const twenty = toBN(STAKE / 10n * 2n);
+      await program.methods.unstake(twenty).accounts({
         user: presale.publicKey, userStake: presaleStake, userTokenAccount: presaleAta,
         stakedVault, vaultAuthority: authPda, config: cfgPda, tokenMint: mint,
         tokenProgram: TOKEN_PROGRAM_ID,
       }).signers([presale]).rpc();
 
       const info = await program.account.userStake.fetch(presaleStake);
-      expect(info.stakedAmount.eq(toBN(STAKE).sub(thirty))).to.be.true;
+      expect(info.stakedAmount.eq(toBN(STAKE / 10n * 7n))).to.be.true;
+      expect(info.totalWithdrawn.eq(toBN(STAKE / 10n * 3n))).to.be.true;
     });
 
     // day 6 (+ buffer) — cumulative 60 % rule holds
     it("day 6 (+ buffer) — cumulative 60 % rule holds", async () => {
       // Already at day 3+, warp 3 more days + buffer
       await warpSeconds(connection, 3 * DAY_SECONDS + CHUNK_SECONDS / 2); // Warp to day 6 + 3 hours
-      const info   = await program.account.userStake.fetch(presaleStake);
-      const unlocked = info.stakedAmount.muln(6).divn(10);  // 60%
+      // 60 % of the original stake minus the 30 % already withdrawn this cycle
+      const unlocked = toBN(STAKE / 10n * 3n);
       await program.methods.unstake(unlocked).accounts({
         user: presale.publicKey, userStake: presaleStake, userTokenAccount: presaleAta,
         stakedVault, vaultAuthority: authPda, config: cfgPda, tokenMint: mint,
@@ -253,7 +256,7 @@ describe("MCar Vesting — PRD v1.1 exhaustive", () => {
   // skip bulk-withdraw vesting tests until time-warp helper is implemented
   describe.skip("public stake — bulk withdraw after idle days", () => {
     before("register public user", async () => {
-      await program.methods.registerUser().accounts({
+      await program.methods.registerUser(new BN(0)).accounts({
         user: pubUser.publicKey, userStake: pubStake, config: cfgPda,
         systemProgram: SystemProgram.programId,
       }).signers([pubUser]).rpc();
@@ -320,7 +323,7 @@ describe("MCar Vesting — PRD v1.1 exhaustive", () => {
 
     it("staker claims reflections successfully", async () => {
       const sol0 = await connection.getBalance(pubUser.publicKey);
-      await program.methods.claimReflections().accounts({
+      await program.methods.claimReflections(false, false).accounts({
         user: pubUser.publicKey, userStake: pubStake, solTreasury: treasuryPda,
         config: cfgPda, systemProgram: SystemProgram.programId,
       }).signers([pubUser]).rpc();
@@ -341,7 +344,7 @@ describe("MCar Vesting — PRD v1.1 exhaustive", () => {
       }).signers([admin]).rpc();
       const idx1 = (await program.account.globalConfig.fetch(cfgPda)).reflectionIndex;
       const solBefore1 = await connection.getBalance(pubUser.publicKey);
-      await program.methods.claimReflections().accounts({
+      await program.methods.claimReflections(false, false).accounts({
         user: pubUser.publicKey, userStake: pubStake, solTreasury: treasuryPda,
         config: cfgPda, systemProgram: SystemProgram.programId,
       }).signers([pubUser]).rpc();
@@ -360,7 +363,7 @@ describe("MCar Vesting — PRD v1.1 exhaustive", () => {
       const idx2 = (await program.account.globalConfig.fetch(cfgPda)).reflectionIndex;
       expect(idx2.gt(idx1)).to.be.true;
       const solBefore2 = await connection.getBalance(pubUser.publicKey);
-      await program.methods.claimReflections().accounts({
+      await program.methods.claimReflections(false, false).accounts({
         user: pubUser.publicKey, userStake: pubStake, solTreasury: treasuryPda,
         config: cfgPda, systemProgram: SystemProgram.programId,
       }).signers([pubUser]).rpc();
@@ -370,6 +373,2479 @@ describe("MCar Vesting — PRD v1.1 exhaustive", () => {
       expect(claimed2).to.be.gt(0);
       expect(claimed2).to.equal(claimed1);
     });
+
+    it("keeper can trigger a claim that pays the owner", async () => {
+      const keeper = Keypair.generate();
+      const sig = await connection.requestAirdrop(keeper.publicKey, LAMPORTS_PER_SOL);
+      await connection.confirmTransaction(sig);
+      const supply = (await getMint(connection, mint)).supply;
+      await provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          SystemProgram.transfer({ fromPubkey: admin.publicKey, toPubkey: treasuryPda, lamports: DEPOSIT })
+        ), [admin]
+      );
+      await program.methods.depositReflectionFunds(toBN(DEPOSIT), toBN(supply)).accounts({
+        admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+      }).signers([admin]).rpc();
+
+      const owner0  = await connection.getBalance(pubUser.publicKey);
+      const keeper0 = await connection.getBalance(keeper.publicKey);
+      await program.methods.claimReflectionsFor().accounts({
+        caller: keeper.publicKey, owner: pubUser.publicKey, userStake: pubStake,
+        solTreasury: treasuryPda, config: cfgPda, systemProgram: SystemProgram.programId,
+      }).signers([keeper]).rpc();
+      expect(await connection.getBalance(pubUser.publicKey)).to.be.greaterThan(owner0);
+      expect(await connection.getBalance(keeper.publicKey)).to.be.at.most(keeper0);
+
+      // The recipient must be the recorded owner
+      await expect(
+        program.methods.claimReflectionsFor().accounts({
+          caller: keeper.publicKey, owner: keeper.publicKey, userStake: pubStake,
+          solTreasury: treasuryPda, config: cfgPda, systemProgram: SystemProgram.programId,
+        }).signers([keeper]).rpc()
+      ).to.be.rejected;
+    });
+
+    it("dry‑run claim leaves index and balances unchanged", async () => {
+      const supply = (await getMint(connection, mint)).supply;
+      await provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          SystemProgram.transfer({ fromPubkey: admin.publicKey, toPubkey: treasuryPda, lamports: DEPOSIT })
+        ), [admin]
+      );
+      await program.methods.depositReflectionFunds(toBN(DEPOSIT), toBN(supply)).accounts({
+        admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+      }).signers([admin]).rpc();
+
+      const stake0    = await program.account.userStake.fetch(pubStake);
+      const sol0      = await connection.getBalance(pubUser.publicKey);
+      const treasury0 = await connection.getBalance(treasuryPda);
+      await program.methods.claimReflections(true, false).accounts({
+        user: pubUser.publicKey, userStake: pubStake, solTreasury: treasuryPda,
+        config: cfgPda, systemProgram: SystemProgram.programId,
+      }).signers([pubUser]).rpc();
+      await program.methods.claimYield(true).accounts({
+        user: pubUser.publicKey, userStake: pubStake, userTokenAccount: pubAta,
+        rewardVault, vaultAuthority: authPda, config: cfgPda, yieldMint: mint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([pubUser]).rpc();
+      const stake1 = await program.account.userStake.fetch(pubStake);
+      expect(stake1.lastClaimedIndex.eq(stake0.lastClaimedIndex)).to.be.true;
+      expect(stake1.unclaimedYield.eq(stake0.unclaimedYield)).to.be.true;
+      expect(stake1.lastYieldClaimTime.eq(stake0.lastYieldClaimTime)).to.be.true;
+      expect(await connection.getBalance(pubUser.publicKey)).to.equal(sol0);
+      expect(await connection.getBalance(treasuryPda)).to.equal(treasury0);
+    });
+
+    it("under-funded deposit is rejected before the index moves", async () => {
+      const cfg0     = await program.account.globalConfig.fetch(cfgPda);
+      const treasury = await connection.getBalance(treasuryPda);
+      // With total_supply == total_staked, the stakers' liability equals the deposit
+      await expect(
+        program.methods.depositReflectionFunds(toBN(treasury + 1_000), cfg0.totalStaked).accounts({
+          admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+        }).signers([admin]).rpc()
+      ).to.be.rejectedWith(/InsufficientReflectionBacking/);
+      const cfg1 = await program.account.globalConfig.fetch(cfgPda);
+      expect(cfg1.reflectionIndex.eq(cfg0.reflectionIndex)).to.be.true;
+    });
+
+    it("deposit is only accepted once the treasury received the SOL", async () => {
+      const supply = (await getMint(connection, mint)).supply;
+      const depositIx = () =>
+        program.methods.depositReflectionFunds(toBN(1_000), toBN(supply)).accounts({
+          admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+        }).signers([admin]).rpc();
+      const cfg0 = await program.account.globalConfig.fetch(cfgPda);
+      await expect(depositIx()).to.be.rejectedWith(/ReflectionDepositNotFunded/);
+
+      await provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          SystemProgram.transfer({ fromPubkey: admin.publicKey, toPubkey: treasuryPda, lamports: 1_000 })
+        ), [admin]);
+      await depositIx();
+      const cfg1 = await program.account.globalConfig.fetch(cfgPda);
+      expect(cfg1.reflectionIndex.gt(cfg0.reflectionIndex)).to.be.true;
+      expect(cfg1.lastTreasuryBalance.toNumber()).to.equal(await connection.getBalance(treasuryPda));
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3b · Views
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("views", () => {
+    it("stake summary reports the mint decimals", async () => {
+      const summary = await program.methods.getStakeSummary().accounts({
+        userStake: pubStake, config: cfgPda, tokenMint: mint,
+      }).view();
+      expect(summary.decimals).to.equal(DECIMALS);
+      // Legacy SPL mint: raw amounts format with decimals alone
+      expect(summary.interestBearing).to.be.false;
+      expect((await program.account.globalConfig.fetch(cfgPda)).interestBearingMint).to.be.false;
+      const info = await program.account.userStake.fetch(pubStake);
+      expect(summary.stakedAmount.eq(info.stakedAmount)).to.be.true;
+    });
+
+    it("get_all_pending agrees with the stake summary", async () => {
+      const accounts = { userStake: pubStake, config: cfgPda, tokenMint: mint };
+      const pending = await program.methods.getAllPending().accounts(accounts).view();
+      const summary = await program.methods.getStakeSummary().accounts(accounts).view();
+      expect(pending.reflectionLamports.eq(summary.pendingReflections)).to.be.true;
+      // Yield keeps accruing between the two simulations, so only bound it
+      expect(pending.yieldTokens.lte(summary.pendingYield)).to.be.true;
+    });
+
+    it("can_claim flags agree with the stake summary", async () => {
+      const accounts = { userStake: pubStake, config: cfgPda, tokenMint: mint };
+      const flags = await program.methods.canClaim().accounts(accounts).view();
+      const summary = await program.methods.getStakeSummary().accounts(accounts).view();
+      expect(flags.hasReflections).to.equal(!summary.pendingReflections.isZero());
+      expect(flags.hasUnlocked).to.equal(!summary.unlockedAmount.isZero());
+      // Yield only grows between simulations, so a set flag must stay set
+      if (flags.hasYield) expect(summary.pendingYield.isZero()).to.be.false;
+    });
+
+    it("can_claim_reflections compares pending reflections with the treasury", async () => {
+      const summary = await program.methods.getStakeSummary().accounts({
+        userStake: pubStake, config: cfgPda, tokenMint: mint,
+      }).view();
+      const covered = await program.methods.canClaimReflections().accounts({
+        userStake: pubStake, config: cfgPda, solTreasury: treasuryPda,
+      }).view();
+      const treasury = await connection.getBalance(treasuryPda);
+      expect(covered).to.equal(!summary.pendingReflections.isZero() && summary.pendingReflections.lten(treasury));
+    });
+
+    it("register_cost matches the rent of a UserStake account", async () => {
+      const cost = await program.methods.registerCost().accounts({ systemProgram: SystemProgram.programId }).view();
+      const size = program.account.userStake.size; // discriminator included
+      expect(cost.toNumber()).to.equal(await connection.getMinimumBalanceForRentExemption(size));
+      expect(cost.toNumber()).to.equal(await connection.getBalance(pubStake));
+    });
+
+    it("get_user_share_bps sums to ~10000 across all stakers", async () => {
+      const positions = (await program.account.userStake.all()).filter(p => !p.account.stakedAmount.isZero());
+      let total = 0;
+      for (const p of positions) {
+        total += await program.methods.getUserShareBps().accounts({
+          userStake: p.publicKey, config: cfgPda, tokenMint: mint,
+        }).view();
+      }
+      // Each share is floored, losing less than 1 bps per staker
+      expect(total).to.be.at.most(10_000);
+      expect(total).to.be.at.least(10_000 - positions.length);
+    });
+
+    it("get_pdas matches client-side derivation", async () => {
+      const pdas = await program.methods.getPdas(new BN(0)).accounts({ user: pubUser.publicKey }).view();
+      expect(pdas.config.equals(cfgPda)).to.be.true;
+      expect(pdas.vaultAuthority.equals(authPda)).to.be.true;
+      expect(pdas.solTreasury.equals(treasuryPda)).to.be.true;
+      expect(pdas.userStake.equals(pubStake)).to.be.true;
+      const [, bump] = PublicKey.findProgramAddressSync([SEED_USER, pubUser.publicKey.toBuffer()], PROGRAM_ID);
+      expect(pdas.userStakeBump).to.equal(bump);
+
+      const second = await program.methods.getPdas(new BN(2)).accounts({ user: pubUser.publicKey }).view();
+      const [position2, bump2] = PublicKey.findProgramAddressSync(
+        [SEED_USER, pubUser.publicKey.toBuffer(), new BN(2).toArrayLike(Buffer, "le", 8)], PROGRAM_ID);
+      expect(second.userStake.equals(position2)).to.be.true;
+      expect(second.userStakeBump).to.equal(bump2);
+    });
+
+    it("yield solvency report matches the reward vault", async () => {
+      const report = await program.methods.checkYieldSolvency().accounts({
+        config: cfgPda, rewardVault,
+      }).view();
+      const vault = (await getAccount(connection, rewardVault)).amount;
+      expect(report.rewardVaultBalance.eq(toBN(vault))).to.be.true;
+      if (!report.dailyLiability.isZero()) {
+        expect(report.daysOfRunway.eq(report.rewardVaultBalance.div(report.dailyLiability))).to.be.true;
+      }
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3c · Role separation
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("admin roles", () => {
+    const operator = Keypair.generate();
+    const manager  = Keypair.generate();
+
+    before("assign roles", async () => {
+      for (const kp of [operator, manager]) {
+        const sig = await connection.requestAirdrop(kp.publicKey, LAMPORTS_PER_SOL);
+        await connection.confirmTransaction(sig);
+      }
+      await program.methods.setReflectionOperator(operator.publicKey).accounts({
+        admin: admin.publicKey, config: cfgPda,
+      }).signers([admin]).rpc();
+      await program.methods.setTreasuryManager(manager.publicKey).accounts({
+        admin: admin.publicKey, config: cfgPda,
+      }).signers([admin]).rpc();
+    });
+
+    it("reflection operator can deposit but not withdraw", async () => {
+      const supply = (await getMint(connection, mint)).supply;
+      await program.methods.depositReflectionFunds(toBN(0), toBN(supply)).accounts({
+        admin: operator.publicKey, config: cfgPda, solTreasury: treasuryPda,
+      }).signers([operator]).rpc();
+      await expect(
+        program.methods.adminWithdrawSol(toBN(1)).accounts({
+          admin: operator.publicKey, config: cfgPda, solTreasury: treasuryPda,
+          systemProgram: SystemProgram.programId,
+        }).signers([operator]).rpc()
+      ).to.be.rejected;
+    });
+
+    it("treasury manager can withdraw but not deposit or assign roles", async () => {
+      await provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          SystemProgram.transfer({ fromPubkey: admin.publicKey, toPubkey: treasuryPda, lamports: 1_000 })
+        ), [admin]);
+      await program.methods.adminWithdrawSol(toBN(1_000)).accounts({
+        admin: manager.publicKey, config: cfgPda, solTreasury: treasuryPda,
+        systemProgram: SystemProgram.programId,
+      }).signers([manager]).rpc();
+      const supply = (await getMint(connection, mint)).supply;
+      await expect(
+        program.methods.depositReflectionFunds(toBN(0), toBN(supply)).accounts({
+          admin: manager.publicKey, config: cfgPda, solTreasury: treasuryPda,
+        }).signers([manager]).rpc()
+      ).to.be.rejected;
+      await expect(
+        program.methods.setTreasuryManager(manager.publicKey).accounts({
+          admin: manager.publicKey, config: cfgPda,
+        }).signers([manager]).rpc()
+      ).to.be.rejected;
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3d · Vault replacement
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("admin_set_vaults", () => {
+    it("replaces vault keys while nothing is staked", async () => {
+      const cfg0 = await program.account.globalConfig.fetch(cfgPda);
+      expect(cfg0.totalStaked.isZero()).to.be.true;
+
+      const newStaked = await createAccount(connection, admin, mint, authPda, Keypair.generate());
+      const newReward = await createAccount(connection, admin, mint, authPda, Keypair.generate());
+      await program.methods.adminSetVaults().accounts({
+        admin: admin.publicKey, config: cfgPda, vaultAuthority: authPda, tokenMint: mint,
+        stakedVault: newStaked, rewardVault: newReward,
+      }).signers([admin]).rpc();
+      const cfg1 = await program.account.globalConfig.fetch(cfgPda);
+      expect(cfg1.stakedVault.equals(newStaked)).to.be.true;
+      expect(cfg1.rewardVault.equals(newReward)).to.be.true;
+
+      // restore the suite's vaults
+      await program.methods.adminSetVaults().accounts({
+        admin: admin.publicKey, config: cfgPda, vaultAuthority: authPda, tokenMint: mint,
+        stakedVault, rewardVault,
+      }).signers([admin]).rpc();
+    });
+
+    it("rejects the update once tokens are staked", async () => {
+      const gifted      = Keypair.generate();
+      const giftedStake = findPda([SEED_USER, gifted.publicKey.toBuffer()], PROGRAM_ID);
+      await program.methods.adminInitializePresaleStake(TOKEN_UNIT).accounts({
+        admin: admin.publicKey, userStake: giftedStake, user: gifted.publicKey, config: cfgPda,
+        sourceTokenAccount: adminAta, stakedVault, tokenMint: mint,
+        tokenProgram: TOKEN_PROGRAM_ID, systemProgram: SystemProgram.programId,
+        rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+      }).signers([admin]).rpc();
+
+      await expect(
+        program.methods.adminSetVaults().accounts({
+          admin: admin.publicKey, config: cfgPda, vaultAuthority: authPda, tokenMint: mint,
+          stakedVault, rewardVault,
+        }).signers([admin]).rpc()
+      ).to.be.rejectedWith(/VaultsInUse/);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3e · Stake receipts
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("stake receipts", () => {
+    const holderOfReceipt = Keypair.generate();
+    const STAKE = 1_000n * 10n**9n;
+    let receiptMint: PublicKey, receiptAta: PublicKey, userAta: PublicKey, userStake: PublicKey;
+
+    before("enable receipts and fund a staker", async () => {
+      const sig = await connection.requestAirdrop(holderOfReceipt.publicKey, 2 * LAMPORTS_PER_SOL);
+      await connection.confirmTransaction(sig);
+      receiptMint = await createMint(connection, admin, authPda, null, 0);
+      receiptAta  = (await getOrCreateAssociatedTokenAccount(connection, admin, receiptMint, holderOfReceipt.publicKey)).address;
+      userAta     = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, holderOfReceipt.publicKey)).address;
+      userStake   = findPda([SEED_USER, holderOfReceipt.publicKey.toBuffer()], PROGRAM_ID);
+      await mintTo(connection, admin, mint, userAta, admin, STAKE);
+
+      await program.methods.setReceiptMint(receiptMint).accounts({
+        admin: admin.publicKey, config: cfgPda,
+      }).signers([admin]).rpc();
+      await program.methods.registerUser(new BN(0)).accounts({
+        user: holderOfReceipt.publicKey, userStake, config: cfgPda,
+        systemProgram: SystemProgram.programId,
+      }).signers([holderOfReceipt]).rpc();
+    });
+
+    after("disable receipts", async () => {
+      await program.methods.setReceiptMint(null).accounts({
+        admin: admin.publicKey, config: cfgPda,
+      }).signers([admin]).rpc();
+    });
+
+    it("staking mints exactly one receipt", async () => {
+      for (const amount of [STAKE / 2n, STAKE / 2n]) {
+        await program.methods.stake(toBN(amount), new BN(0), false).accounts({
+          user: holderOfReceipt.publicKey, userStake, userTokenAccount: userAta,
+          stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+          vaultAuthority: authPda, receiptMint, userReceiptAccount: receiptAta,
+          receiptTokenProgram: TOKEN_PROGRAM_ID,
+        }).signers([holderOfReceipt]).rpc();
+      }
+      expect((await getAccount(connection, receiptAta)).amount).equals(1n);
+    });
+
+    it("staking without receipt accounts is rejected while receipts are enabled", async () => {
+      await expect(
+        program.methods.stake(toBN(1), new BN(0), false).accounts({
+          user: holderOfReceipt.publicKey, userStake, userTokenAccount: userAta,
+          stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+        }).signers([holderOfReceipt]).rpc()
+      ).to.be.rejectedWith(/ReceiptAccountsMissing/);
+    });
+
+    // skip full-unstake burn until time-warp helper is implemented
+    it.skip("full unstake burns the receipt", async () => {
+      await warpDays(connection, 8);
+      const info = await program.account.userStake.fetch(userStake);
+      await program.methods.unstake(info.stakedAmount).accounts({
+        user: holderOfReceipt.publicKey, userStake, userTokenAccount: userAta,
+        stakedVault, vaultAuthority: authPda, config: cfgPda, tokenMint: mint,
+        tokenProgram: TOKEN_PROGRAM_ID, receiptMint, userReceiptAccount: receiptAta,
+        receiptTokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([holderOfReceipt]).rpc();
+      expect((await getAccount(connection, receiptAta)).amount).equals(0n);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3f · Reflection freeze
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("reflections frozen", () => {
+    const setFrozen = (frozen: boolean) =>
+      program.methods.setReflectionsFrozen(frozen).accounts({
+        admin: admin.publicKey, config: cfgPda,
+      }).signers([admin]).rpc();
+
+    after("unfreeze", async () => { await setFrozen(false); });
+
+    it("blocks deposits while staking and yield claims still work", async () => {
+      await setFrozen(true);
+      const supply = (await getMint(connection, mint)).supply;
+      const idx0 = (await program.account.globalConfig.fetch(cfgPda)).reflectionIndex;
+      await expect(
+        program.methods.depositReflectionFunds(toBN(LAMPORTS_PER_SOL), toBN(supply)).accounts({
+          admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+        }).signers([admin]).rpc()
+      ).to.be.rejectedWith(/ReflectionsFrozen/);
+      expect((await program.account.globalConfig.fetch(cfgPda)).reflectionIndex.eq(idx0)).to.be.true;
+
+      const user  = Keypair.generate();
+      const sig   = await connection.requestAirdrop(user.publicKey, LAMPORTS_PER_SOL);
+      await connection.confirmTransaction(sig);
+      const ata   = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+      const stake = findPda([SEED_USER, user.publicKey.toBuffer()], PROGRAM_ID);
+      await mintTo(connection, admin, mint, ata, admin, TOKEN_UNIT.toNumber());
+      await program.methods.registerUser(new BN(0)).accounts({
+        user: user.publicKey, userStake: stake, config: cfgPda,
+        systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+      await program.methods.stake(TOKEN_UNIT, new BN(0), false).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+      await program.methods.claimYield(true).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        rewardVault, vaultAuthority: authPda, config: cfgPda, yieldMint: mint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+      expect((await program.account.userStake.fetch(stake)).stakedAmount.eq(TOKEN_UNIT)).to.be.true;
+    });
+
+    it("already-accrued reflections stay claimable while deposits are paused", async () => {
+      await setFrozen(false);
+      await provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          SystemProgram.transfer({ fromPubkey: admin.publicKey, toPubkey: treasuryPda, lamports: LAMPORTS_PER_SOL })
+        ), [admin]);
+      const supply = (await getMint(connection, mint)).supply;
+      await program.methods.depositReflectionFunds(toBN(LAMPORTS_PER_SOL), toBN(supply)).accounts({
+        admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+      }).signers([admin]).rpc();
+
+      await setFrozen(true);
+      const sol0 = await connection.getBalance(pubUser.publicKey);
+      await program.methods.claimReflections(false, false).accounts({
+        user: pubUser.publicKey, userStake: pubStake, solTreasury: treasuryPda,
+        config: cfgPda, systemProgram: SystemProgram.programId,
+      }).signers([pubUser]).rpc();
+      expect(await connection.getBalance(pubUser.publicKey)).to.be.greaterThan(sol0);
+      await expect(
+        program.methods.depositReflectionFunds(toBN(1), toBN(supply)).accounts({
+          admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+        }).signers([admin]).rpc()
+      ).to.be.rejectedWith(/ReflectionsFrozen/);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3g · Presale top-up of an existing position
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("presale init on a registered user", () => {
+    it("keeps accrued yield and reflection checkpoint", async () => {
+      const user  = Keypair.generate();
+      const sig   = await connection.requestAirdrop(user.publicKey, LAMPORTS_PER_SOL);
+      await connection.confirmTransaction(sig);
+      const ata   = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+      const stake = findPda([SEED_USER, user.publicKey.toBuffer()], PROGRAM_ID);
+      await mintTo(connection, admin, mint, ata, admin, TOKEN_UNIT.toNumber());
+      await program.methods.registerUser(new BN(0)).accounts({
+        user: user.publicKey, userStake: stake, config: cfgPda,
+        systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+      await program.methods.stake(TOKEN_UNIT, new BN(0), false).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+      // Let some yield accrue on the registered position
+      await new Promise((r) => setTimeout(r, 2000));
+      const before = await program.account.userStake.fetch(stake);
+
+      await program.methods.adminInitializePresaleStake(TOKEN_UNIT).accounts({
+        admin: admin.publicKey, userStake: stake, user: user.publicKey, config: cfgPda,
+        sourceTokenAccount: adminAta, stakedVault, tokenMint: mint,
+        tokenProgram: TOKEN_PROGRAM_ID, systemProgram: SystemProgram.programId,
+        rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+      }).signers([admin]).rpc();
+
+      const after = await program.account.userStake.fetch(stake);
+      expect(after.stakedAmount.eq(TOKEN_UNIT.muln(2))).to.be.true;
+      expect(after.lastClaimedIndex.eq(before.lastClaimedIndex)).to.be.true;
+      expect(after.unclaimedYield.gte(before.unclaimedYield)).to.be.true;
+      expect(after.owner.equals(user.publicKey)).to.be.true;
+    });
+
+    it("stake_for credits the beneficiary and debits the benefactor", async () => {
+      const beneficiary = Keypair.generate();
+      const stake = findPda([SEED_USER, beneficiary.publicKey.toBuffer()], PROGRAM_ID);
+      const benefactorBefore = (await getAccount(connection, adminAta)).amount;
+
+      await program.methods.stakeFor(TOKEN_UNIT, beneficiary.publicKey, new BN(0)).accounts({
+        benefactor: admin.publicKey, userStake: stake, benefactorTokenAccount: adminAta,
+        stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      }).signers([admin]).rpc();
+      await program.methods.stakeFor(TOKEN_UNIT, beneficiary.publicKey, new BN(0)).accounts({
+        benefactor: admin.publicKey, userStake: stake, benefactorTokenAccount: adminAta,
+        stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      }).signers([admin]).rpc();
+
+      const info = await program.account.userStake.fetch(stake);
+      expect(info.owner.equals(beneficiary.publicKey)).to.be.true;
+      expect(info.stakedAmount.eq(TOKEN_UNIT.muln(2))).to.be.true;
+      const benefactorAfter = (await getAccount(connection, adminAta)).amount;
+      expect(benefactorBefore - benefactorAfter).to.equal(BigInt(TOKEN_UNIT.muln(2).toString()));
+    });
+
+    it("stake_for rejects the benefactor as beneficiary", async () => {
+      const stake = findPda([SEED_USER, admin.publicKey.toBuffer()], PROGRAM_ID);
+      await expect(
+        program.methods.stakeFor(TOKEN_UNIT, admin.publicKey, new BN(0)).accounts({
+          benefactor: admin.publicKey, userStake: stake, benefactorTokenAccount: adminAta,
+          stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+        }).signers([admin]).rpc()
+      ).to.be.rejectedWith(/SelfStakeFor/);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3h · Reflection sink
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("reflection sink", () => {
+    const sink = Keypair.generate();
+
+    before("fund sink so it can receive small transfers", async () => {
+      const sig = await connection.requestAirdrop(sink.publicKey, LAMPORTS_PER_SOL);
+      await connection.confirmTransaction(sig);
+      await program.methods.setReflectionSink(sink.publicKey, 2_500).accounts({
+        admin: admin.publicKey, config: cfgPda,
+      }).signers([admin]).rpc();
+    });
+
+    after("disable sink", async () => {
+      await program.methods.setReflectionSink(null, 0).accounts({
+        admin: admin.publicKey, config: cfgPda,
+      }).signers([admin]).rpc();
+    });
+
+    it("sink receives its share and the index uses the remainder", async () => {
+      const DEPOSIT = LAMPORTS_PER_SOL;
+      await provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          SystemProgram.transfer({ fromPubkey: admin.publicKey, toPubkey: treasuryPda, lamports: DEPOSIT })
+        ), [admin]);
+      const cfg0   = await program.account.globalConfig.fetch(cfgPda);
+      const sink0  = await connection.getBalance(sink.publicKey);
+      const supply = (await getMint(connection, mint)).supply;
+
+      await program.methods.depositReflectionFunds(toBN(DEPOSIT), toBN(supply)).accounts({
+        admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+        reflectionSink: sink.publicKey, systemProgram: SystemProgram.programId,
+      }).signers([admin]).rpc();
+
+      expect(await connection.getBalance(sink.publicKey) - sink0).to.equal(DEPOSIT / 4);
+      const cfg1 = await program.account.globalConfig.fetch(cfgPda);
+      expect(cfg1.reflectionIndex.sub(cfg0.reflectionIndex).gt(new BN(0))).to.be.true;
+      // External yield funding: nothing is earmarked
+      expect(cfg1.reflectionEarmarkedForYield.eq(cfg0.reflectionEarmarkedForYield)).to.be.true;
+    });
+
+    it("earmarks the sink share for yield under reflection conversion", async () => {
+      const setSource = (source: number) =>
+        program.methods.setYieldFundingSource(source).accounts({
+          admin: admin.publicKey, config: cfgPda,
+        }).signers([admin]).rpc();
+      await expect(setSource(2)).to.be.rejectedWith(/InvalidYieldFundingSource/);
+      await setSource(1);
+      try {
+        const DEPOSIT = LAMPORTS_PER_SOL;
+        await provider.sendAndConfirm(
+          new anchor.web3.Transaction().add(
+            SystemProgram.transfer({ fromPubkey: admin.publicKey, toPubkey: treasuryPda, lamports: DEPOSIT })
+          ), [admin]);
+        const cfg0   = await program.account.globalConfig.fetch(cfgPda);
+        const supply = (await getMint(connection, mint)).supply;
+        await program.methods.depositReflectionFunds(toBN(DEPOSIT), toBN(supply)).accounts({
+          admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+          reflectionSink: sink.publicKey, systemProgram: SystemProgram.programId,
+        }).signers([admin]).rpc();
+        const cfg1 = await program.account.globalConfig.fetch(cfgPda);
+        expect(cfg1.reflectionEarmarkedForYield.sub(cfg0.reflectionEarmarkedForYield).toNumber()).to.equal(DEPOSIT / 4);
+      } finally {
+        await setSource(0);
+      }
+    });
+
+    it("deposit without the sink account is rejected", async () => {
+      const supply = (await getMint(connection, mint)).supply;
+      await expect(
+        program.methods.depositReflectionFunds(toBN(1_000), toBN(supply)).accounts({
+          admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+        }).signers([admin]).rpc()
+      ).to.be.rejectedWith(/ReflectionSinkMissing/);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3i · Pooled reflection claims
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("pooled reflection claims", () => {
+    const users = [Keypair.generate(), Keypair.generate()];
+    const stakes: PublicKey[] = [];
+    const pool  = Keypair.generate();
+
+    before("two stakers and a deposit", async () => {
+      for (const u of [...users, pool]) {
+        const sig = await connection.requestAirdrop(u.publicKey, LAMPORTS_PER_SOL);
+        await connection.confirmTransaction(sig);
+      }
+      for (const u of users) {
+        const ata   = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, u.publicKey)).address;
+        const stake = findPda([SEED_USER, u.publicKey.toBuffer()], PROGRAM_ID);
+        stakes.push(stake);
+        await mintTo(connection, admin, mint, ata, admin, TOKEN_UNIT.toNumber());
+        await program.methods.registerUser(new BN(0)).accounts({
+          user: u.publicKey, userStake: stake, config: cfgPda,
+          systemProgram: SystemProgram.programId,
+        }).signers([u]).rpc();
+        await program.methods.stake(TOKEN_UNIT, new BN(0), false).accounts({
+          user: u.publicKey, userStake: stake, userTokenAccount: ata,
+          stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+        }).signers([u]).rpc();
+      }
+      await provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          SystemProgram.transfer({ fromPubkey: admin.publicKey, toPubkey: treasuryPda, lamports: LAMPORTS_PER_SOL })
+        ), [admin]);
+      const cfg = await program.account.globalConfig.fetch(cfgPda);
+      await program.methods.depositReflectionFunds(toBN(LAMPORTS_PER_SOL), cfg.totalStaked.muln(2)).accounts({
+        admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+      }).signers([admin]).rpc();
+    });
+
+    it("sums both users' reflections into one transfer to the pool", async () => {
+      let expected = 0;
+      for (const stake of stakes) {
+        const pending = await program.methods.getAllPending().accounts({
+          userStake: stake, config: cfgPda, tokenMint: mint,
+        }).view();
+        expected += pending.reflectionLamports.toNumber();
+      }
+      expect(expected).to.be.greaterThan(0);
+      const pool0 = await connection.getBalance(pool.publicKey);
+
+      await program.methods.claimReflectionsPooled().accounts({
+        caller: admin.publicKey, pool: pool.publicKey, solTreasury: treasuryPda,
+        config: cfgPda, systemProgram: SystemProgram.programId,
+      }).remainingAccounts(users.flatMap((u, i) => [
+        { pubkey: stakes[i],   isSigner: false, isWritable: true },
+        { pubkey: u.publicKey, isSigner: true,  isWritable: false },
+      ])).signers([admin, ...users]).rpc();
+
+      expect(await connection.getBalance(pool.publicKey) - pool0).to.equal(expected);
+      for (const stake of stakes) {
+        const info = await program.account.userStake.fetch(stake);
+        expect(info.unclaimedReflections.isZero()).to.be.true;
+      }
+    });
+
+    it("rejects a position whose owner did not sign", async () => {
+      await expect(
+        program.methods.claimReflectionsPooled().accounts({
+          caller: admin.publicKey, pool: pool.publicKey, solTreasury: treasuryPda,
+          config: cfgPda, systemProgram: SystemProgram.programId,
+        }).remainingAccounts([
+          { pubkey: stakes[0], isSigner: false, isWritable: true },
+          { pubkey: users[0].publicKey, isSigner: false, isWritable: false },
+        ]).signers([admin]).rpc()
+      ).to.be.rejectedWith(/Unauthorized/);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3j · Pause & treasury migration
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("treasury migration", () => {
+    const setPaused = (paused: boolean) =>
+      program.methods.setPaused(paused).accounts({
+        admin: admin.publicKey, config: cfgPda,
+      }).signers([admin]).rpc();
+    const treasuryFor = (version: number) =>
+      version === 0 ? treasuryPda : findPda([SEED_TREASURY, Buffer.from([version])], PROGRAM_ID);
+    const migrate = (from: number, to: number) =>
+      program.methods.migrateTreasury(to).accounts({
+        admin: admin.publicKey, config: cfgPda,
+        oldTreasury: treasuryFor(from), newTreasury: treasuryFor(to),
+        systemProgram: SystemProgram.programId,
+      }).signers([admin]).rpc();
+
+    after("restore the original treasury", async () => {
+      const cfg = await program.account.globalConfig.fetch(cfgPda);
+      if (cfg.solTreasuryVersion !== 0) {
+        if (!cfg.paused) await setPaused(true);
+        await migrate(cfg.solTreasuryVersion, 0);
+      }
+      await setPaused(false);
+    });
+
+    it("requires the program to be paused", async () => {
+      await expect(migrate(0, 1)).to.be.rejectedWith(/NotPaused/);
+    });
+
+    it("blocks user operations while paused", async () => {
+      await setPaused(true);
+      await expect(
+        program.methods.claimReflections(false, false).accounts({
+          user: pubUser.publicKey, userStake: pubStake, solTreasury: treasuryPda,
+          config: cfgPda, systemProgram: SystemProgram.programId,
+        }).signers([pubUser]).rpc()
+      ).to.be.rejectedWith(/ProgramPaused/);
+    });
+
+    it("moves the full balance and claims use the new PDA", async () => {
+      const balance = await connection.getBalance(treasuryPda);
+      await migrate(0, 1);
+      expect(await connection.getBalance(treasuryPda)).to.equal(0);
+      expect(await connection.getBalance(treasuryFor(1))).to.equal(balance);
+      const cfg = await program.account.globalConfig.fetch(cfgPda);
+      expect(cfg.solTreasuryVersion).to.equal(1);
+
+      const pdas = await program.methods.getPdas(new BN(0)).accounts({
+        user: pubUser.publicKey, config: cfgPda,
+      }).view();
+      expect(pdas.solTreasury.equals(treasuryFor(1))).to.be.true;
+
+      await setPaused(false);
+      await provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          SystemProgram.transfer({ fromPubkey: admin.publicKey, toPubkey: treasuryFor(1), lamports: LAMPORTS_PER_SOL })
+        ), [admin]);
+      const supply = (await getMint(connection, mint)).supply;
+      await program.methods.depositReflectionFunds(toBN(LAMPORTS_PER_SOL), toBN(supply)).accounts({
+        admin: admin.publicKey, config: cfgPda, solTreasury: treasuryFor(1),
+      }).signers([admin]).rpc();
+      await expect(
+        program.methods.claimReflections(false, false).accounts({
+          user: pubUser.publicKey, userStake: pubStake, solTreasury: treasuryPda,
+          config: cfgPda, systemProgram: SystemProgram.programId,
+        }).signers([pubUser]).rpc()
+      ).to.be.rejectedWith(/ConstraintSeeds/);
+      await program.methods.claimReflections(false, false).accounts({
+        user: pubUser.publicKey, userStake: pubStake, solTreasury: treasuryFor(1),
+        config: cfgPda, systemProgram: SystemProgram.programId,
+      }).signers([pubUser]).rpc();
+    });
+
+    it("rejects migrating to the current version", async () => {
+      await setPaused(true);
+      await expect(migrate(1, 1)).to.be.rejectedWith(/InvalidTreasuryVersion/);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3k · Governance approval (Ed25519 precompile)
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("governance approval", () => {
+    const governance = Keypair.generate();
+
+    // Governance signs the admin instruction's data followed by the config nonce
+    const approve = async (ix: anchor.web3.TransactionInstruction, signer: Keypair) => {
+      const { governanceNonce } = await program.account.globalConfig.fetch(cfgPda);
+      const message = Buffer.concat([ix.data, governanceNonce.toArrayLike(Buffer, "le", 8)]);
+      return Ed25519Program.createInstructionWithPrivateKey({ privateKey: signer.secretKey, message });
+    };
+    const withdrawIx = (amount: number) =>
+      program.methods.adminWithdrawSol(toBN(amount)).accounts({
+        admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+        systemProgram: SystemProgram.programId, instructionsSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
+      }).instruction();
+    const setKeyIx = (key: PublicKey | null) =>
+      program.methods.setGovernanceKey(key).accounts({
+        admin: admin.publicKey, config: cfgPda, instructionsSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
+      }).instruction();
+
+    before("fund treasury and set the governance key", async () => {
+      await provider.sendAndConfirm(
+        new Transaction().add(
+          SystemProgram.transfer({ fromPubkey: admin.publicKey, toPubkey: treasuryPda, lamports: 10_000 })
+        ), [admin]);
+      await provider.sendAndConfirm(new Transaction().add(await setKeyIx(governance.publicKey)), [admin]);
+    });
+
+    after("clear the governance key", async () => {
+      const ix = await setKeyIx(null);
+      await provider.sendAndConfirm(new Transaction().add(await approve(ix, governance), ix), [admin]);
+    });
+
+    it("rejects a withdrawal without approval", async () => {
+      await expect(
+        provider.sendAndConfirm(new Transaction().add(await withdrawIx(1_000)), [admin])
+      ).to.be.rejectedWith(/GovernanceApprovalMissing|0x/);
+    });
+
+    it("rejects a signature from another key", async () => {
+      const ix = await withdrawIx(1_000);
+      await expect(
+        provider.sendAndConfirm(new Transaction().add(await approve(ix, Keypair.generate()), ix), [admin])
+      ).to.be.rejectedWith(/GovernanceApprovalMissing|0x/);
+    });
+
+    it("accepts a valid governance signature once", async () => {
+      const ix = await withdrawIx(1_000);
+      const approval = await approve(ix, governance);
+      const treasury0 = await connection.getBalance(treasuryPda);
+      await provider.sendAndConfirm(new Transaction().add(approval, ix), [admin]);
+      expect(treasury0 - await connection.getBalance(treasuryPda)).to.equal(1_000);
+      const cfg = await program.account.globalConfig.fetch(cfgPda);
+      expect(cfg.governanceNonce.toNumber()).to.be.greaterThan(0);
+
+      // The nonce moved on, so the same approval cannot be replayed
+      await expect(
+        provider.sendAndConfirm(new Transaction().add(approval, await withdrawIx(1_000)), [admin])
+      ).to.be.rejectedWith(/GovernanceApprovalMissing|0x/);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3l · Yield paid in a separate mint
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("distinct yield mint", () => {
+    let usdMint: PublicKey, usdVault: PublicKey, userUsdAta: PublicKey;
+    const liabilitySum = findPda([Buffer.from("liability_sum")], PROGRAM_ID);
+    const setYieldMint = (yieldMint: PublicKey, vault: PublicKey, withSum = true) =>
+      program.methods.setYieldMint().accounts({
+        admin: admin.publicKey, config: cfgPda, vaultAuthority: authPda,
+        yieldMint, rewardVault: vault, ...(withSum ? { liabilitySum } : {}),
+      }).signers([admin]).rpc();
+    // Settlement before a mint change: pause, pay out banked yield, prove nothing is owed.
+    // The suite settles pubUser's position, the only one claiming yield in these tests.
+    const settleYield = async (fromMint: PublicKey, fromVault: PublicKey, userAta: PublicKey) => {
+      await program.methods.prepareYieldPause(true).accounts({ admin: admin.publicKey, config: cfgPda })
+        .remainingAccounts([{ pubkey: pubStake, isSigner: false, isWritable: true }])
+        .signers([admin]).rpc();
+      if ((await program.account.userStake.fetch(pubStake)).unclaimedYield.gtn(0)) {
+        await program.methods.claimYield(false).accounts({
+          user: pubUser.publicKey, userStake: pubStake, userTokenAccount: userAta,
+          rewardVault: fromVault, vaultAuthority: authPda, config: cfgPda, yieldMint: fromMint,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        }).signers([pubUser]).rpc();
+      }
+      await program.methods.resetLiabilitySum().accounts({
+        admin: admin.publicKey, config: cfgPda, liabilitySum, systemProgram: SystemProgram.programId,
+      }).signers([admin]).rpc();
+      await program.methods.sumLiabilities().accounts({ liabilitySum, config: cfgPda })
+        .remainingAccounts([{ pubkey: pubStake, isSigner: false, isWritable: false }]).rpc();
+    };
+    const resumeYield = () =>
+      program.methods.resumeYield().accounts({ admin: admin.publicKey, config: cfgPda }).signers([admin]).rpc();
+
+    before("create and fund a stablecoin reward vault", async () => {
+      usdMint    = await createMint(connection, admin, admin.publicKey, null, 6);
+      usdVault   = (await getOrCreateAssociatedTokenAccount(connection, admin, usdMint, authPda, true)).address;
+      userUsdAta = (await getOrCreateAssociatedTokenAccount(connection, admin, usdMint, pubUser.publicKey)).address;
+      await mintTo(connection, admin, usdMint, usdVault, admin, 1_000_000_000_000n);
+    });
+
+    after("pay yield in the staked token again", async () => {
+      await settleYield(usdMint, usdVault, userUsdAta);
+      await setYieldMint(mint, rewardVault);
+      await resumeYield();
+    });
+
+    it("rejects a reward vault of another mint", async () => {
+      await expect(setYieldMint(usdMint, rewardVault)).to.be.rejected;
+    });
+
+    it("rejects a mint change while yield is owed in the old mint", async () => {
+      await expect(setYieldMint(usdMint, usdVault, false)).to.be.rejectedWith(/YieldNotPaused/);
+      await program.methods.prepareYieldPause(true).accounts({ admin: admin.publicKey, config: cfgPda })
+        .remainingAccounts([{ pubkey: pubStake, isSigner: false, isWritable: true }])
+        .signers([admin]).rpc();
+      await expect(setYieldMint(usdMint, usdVault, false)).to.be.rejectedWith(/YieldLiabilityOutstanding/);
+      await resumeYield();
+    });
+
+    it("pays yield from the stablecoin vault", async () => {
+      await settleYield(mint, rewardVault, pubAta);
+      await setYieldMint(usdMint, usdVault);
+      await resumeYield();
+      await new Promise(resolve => setTimeout(resolve, 2_000));
+      const cfg = await program.account.globalConfig.fetch(cfgPda);
+      expect(cfg.yieldMint.equals(usdMint)).to.be.true;
+      expect(cfg.tokenMint.equals(mint)).to.be.true;
+
+      // The staked-token account no longer qualifies as a yield destination
+      await expect(
+        program.methods.claimYield(false).accounts({
+          user: pubUser.publicKey, userStake: pubStake, userTokenAccount: pubAta,
+          rewardVault: usdVault, vaultAuthority: authPda, config: cfgPda, yieldMint: usdMint,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        }).signers([pubUser]).rpc()
+      ).to.be.rejectedWith(/ConstraintTokenMint/);
+
+      const mcar0 = (await getAccount(connection, pubAta)).amount;
+      await program.methods.claimYield(false).accounts({
+        user: pubUser.publicKey, userStake: pubStake, userTokenAccount: userUsdAta,
+        rewardVault: usdVault, vaultAuthority: authPda, config: cfgPda, yieldMint: usdMint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([pubUser]).rpc();
+      expect((await getAccount(connection, userUsdAta)).amount > 0n).to.be.true;
+      expect((await getAccount(connection, pubAta)).amount).to.equal(mcar0);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3m · Yield rate history
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("yield rate history", () => {
+    let originalBps: number;
+    const setRate = (bps: number) =>
+      program.methods.setYieldRate(bps).accounts({
+        admin: admin.publicKey, config: cfgPda,
+      }).signers([admin]).rpc();
+    const history = () => program.methods.getRateHistory().accounts({ config: cfgPda }).view();
+
+    before(async () => {
+      originalBps = (await program.account.globalConfig.fetch(cfgPda)).yieldRateBps;
+    });
+
+    after("restore the suite's rate", async () => { await setRate(originalBps); });
+
+    it("records each change with the previous rate", async () => {
+      const before = (await history()).length;
+      await setRate(1_500);
+      await setRate(900);
+      const entries = await history();
+      expect(entries.length).to.equal(Math.min(before + 2, 10));
+      const [a, b] = entries.slice(-2);
+      expect(a.oldBps).to.equal(originalBps);
+      expect(a.newBps).to.equal(1_500);
+      expect(b.oldBps).to.equal(1_500);
+      expect(b.newBps).to.equal(900);
+      expect(b.timestamp.gte(a.timestamp)).to.be.true;
+    });
+
+    it("keeps only the last 10 changes", async () => {
+      for (let i = 1; i <= 12; i++) await setRate(100 * i);
+      const entries = await history();
+      expect(entries.length).to.equal(10);
+      expect(entries.map(e => e.newBps)).to.deep.equal([300, 400, 500, 600, 700, 800, 900, 1000, 1100, 1200]);
+      expect((await program.account.globalConfig.fetch(cfgPda)).rateChangesRecorded.toNumber()).to.be.at.least(14);
+    });
+
+    it("rejects rates above the cap", async () => {
+      await expect(setRate(20_001)).to.be.rejectedWith(/YieldRateTooHigh/);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3n · Yield compounding
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("compound yield", () => {
+    let smallVault: PublicKey;
+    const useRewardVault = (vault: PublicKey) =>
+      program.methods.setYieldMint().accounts({
+        admin: admin.publicKey, config: cfgPda, vaultAuthority: authPda,
+        yieldMint: mint, rewardVault: vault,
+      }).signers([admin]).rpc();
+    const compound = (vault: PublicKey) =>
+      program.methods.compoundYield().accounts({
+        user: pubUser.publicKey, userStake: pubStake, vaultAuthority: authPda,
+        rewardVault: vault, stakedVault, config: cfgPda, tokenMint: mint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([pubUser]).rpc();
+
+    before("point the config at an empty reward vault", async () => {
+      smallVault = await createAccount(connection, admin, mint, authPda, Keypair.generate());
+      await useRewardVault(smallVault);
+    });
+
+    after("restore the suite's reward vault", async () => { await useRewardVault(rewardVault); });
+
+    it("rejects compounding from an empty reward vault", async () => {
+      await expect(compound(smallVault)).to.be.rejectedWith(/InsufficientRewardVault/);
+    });
+
+    it("compounds what a partially funded vault holds and keeps the rest", async () => {
+      await mintTo(connection, admin, mint, smallVault, admin, 1);
+      const staked0 = (await program.account.userStake.fetch(pubStake)).stakedAmount;
+      const total0  = (await program.account.globalConfig.fetch(cfgPda)).totalStaked;
+      await compound(smallVault);
+      const info = await program.account.userStake.fetch(pubStake);
+      expect(info.stakedAmount.sub(staked0).toNumber()).to.equal(1);
+      expect(info.unclaimedYield.gt(new BN(0))).to.be.true;
+      expect((await program.account.globalConfig.fetch(cfgPda)).totalStaked.sub(total0).toNumber()).to.equal(1);
+      expect((await getAccount(connection, smallVault)).amount).to.equal(0n);
+    });
+
+    it("compounds everything from a funded vault", async () => {
+      await useRewardVault(rewardVault);
+      const staked0 = (await program.account.userStake.fetch(pubStake)).stakedAmount;
+      const vault0  = (await getAccount(connection, stakedVault)).amount;
+      await compound(rewardVault);
+      const info = await program.account.userStake.fetch(pubStake);
+      expect(info.unclaimedYield.isZero()).to.be.true;
+      const moved = (await getAccount(connection, stakedVault)).amount - vault0;
+      expect(info.stakedAmount.sub(staked0).toString()).to.equal(moved.toString());
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3o · Unlock curve
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("unlock curve", () => {
+    const setCurve = (curve: number) =>
+      program.methods.setUnlockCurve(curve).accounts({
+        admin: admin.publicKey, config: cfgPda,
+      }).signers([admin]).rpc();
+
+    after("back to linear", async () => { await setCurve(0); });
+
+    it("switches to the exponential curve and rejects unknown ones", async () => {
+      await setCurve(1);
+      expect((await program.account.globalConfig.fetch(cfgPda)).unlockCurve).to.equal(1);
+      await expect(setCurve(3)).to.be.rejectedWith(/InvalidUnlockCurve/);
+    });
+
+    it("switches to the continuous curve", async () => {
+      await setCurve(2);
+      expect((await program.account.globalConfig.fetch(cfgPda)).unlockCurve).to.equal(2);
+    });
+
+    it("rejects non-admin callers", async () => {
+      await expect(
+        program.methods.setUnlockCurve(1).accounts({
+          admin: pubUser.publicKey, config: cfgPda,
+        }).signers([pubUser]).rpc()
+      ).to.be.rejectedWith(/Unauthorized/);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3p · Accrue reflections, claim once
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("accrue reflections", () => {
+    const deposit = async (lamports: number) => {
+      await provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          SystemProgram.transfer({ fromPubkey: admin.publicKey, toPubkey: treasuryPda, lamports })
+        ), [admin]);
+      const supply = (await getMint(connection, mint)).supply;
+      await program.methods.depositReflectionFunds(toBN(lamports), toBN(supply)).accounts({
+        admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+      }).signers([admin]).rpc();
+    };
+    const accrue = () =>
+      program.methods.accrueReflections().accounts({
+        user: pubUser.publicKey, userStake: pubStake, config: cfgPda,
+      }).signers([pubUser]).rpc();
+
+    it("accrues several deposits and pays the total in one claim", async () => {
+      const start = await program.account.userStake.fetch(pubStake);
+      await deposit(LAMPORTS_PER_SOL);
+      await accrue();
+      const first = await program.account.userStake.fetch(pubStake);
+      expect(first.unclaimedReflections.gt(start.unclaimedReflections)).to.be.true;
+      expect(first.lastClaimedIndex.eq((await program.account.globalConfig.fetch(cfgPda)).reflectionIndex)).to.be.true;
+
+      await deposit(LAMPORTS_PER_SOL);
+      await accrue();
+      const second = await program.account.userStake.fetch(pubStake);
+      expect(second.unclaimedReflections.gt(first.unclaimedReflections)).to.be.true;
+
+      const treasury0 = await connection.getBalance(treasuryPda);
+      await program.methods.claimReflections(false, false).accounts({
+        user: pubUser.publicKey, userStake: pubStake, solTreasury: treasuryPda,
+        config: cfgPda, systemProgram: SystemProgram.programId,
+      }).signers([pubUser]).rpc();
+      expect(treasury0 - await connection.getBalance(treasuryPda)).to.equal(second.unclaimedReflections.toNumber());
+      expect((await program.account.userStake.fetch(pubStake)).unclaimedReflections.isZero()).to.be.true;
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3q · Freezing a user
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("admin freeze user", () => {
+    const user  = Keypair.generate();
+    const stake = findPda([SEED_USER, user.publicKey.toBuffer()], PROGRAM_ID);
+    let ata: PublicKey;
+    const setFrozen = (frozen: boolean) =>
+      (frozen ? program.methods.adminFreezeUser() : program.methods.adminUnfreezeUser()).accounts({
+        admin: admin.publicKey, config: cfgPda, userStake: stake,
+      }).signers([admin]).rpc();
+    const stakeTokens = () =>
+      program.methods.stake(TOKEN_UNIT, new BN(0), false).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+
+    before("register and stake", async () => {
+      await connection.confirmTransaction(await connection.requestAirdrop(user.publicKey, LAMPORTS_PER_SOL));
+      ata = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+      await mintTo(connection, admin, mint, ata, admin, 3n * BigInt(TOKEN_UNIT.toString()));
+      await program.methods.registerUser(new BN(0)).accounts({
+        user: user.publicKey, userStake: stake, config: cfgPda,
+        systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+      await stakeTokens();
+    });
+
+    it("blocks stake and unstake while frozen but keeps settled rewards claimable", async () => {
+      await setFrozen(true);
+      const frozen = await program.account.userStake.fetch(stake);
+      expect(frozen.frozen).to.be.true;
+
+      await expect(stakeTokens()).to.be.rejectedWith(/UserFrozen/);
+      await expect(
+        program.methods.unstake(toBN(1)).accounts({
+          user: user.publicKey, userStake: stake, userTokenAccount: ata,
+          vaultAuthority: authPda, stakedVault, config: cfgPda, tokenMint: mint,
+          tokenProgram: TOKEN_PROGRAM_ID,
+        }).signers([user]).rpc()
+      ).to.be.rejectedWith(/UserFrozen/);
+      await expect(setFrozen(true)).to.be.rejectedWith(/UserFrozen/);
+
+      // Principal untouched; the settled yield can still be claimed
+      expect(frozen.stakedAmount.eq(TOKEN_UNIT)).to.be.true;
+      await program.methods.claimYield(true).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        rewardVault, vaultAuthority: authPda, config: cfgPda, yieldMint: mint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+    });
+
+    it("allows staking again after unfreezing", async () => {
+      await setFrozen(false);
+      await stakeTokens();
+      const info = await program.account.userStake.fetch(stake);
+      expect(info.frozen).to.be.false;
+      expect(info.stakedAmount.eq(TOKEN_UNIT.muln(2))).to.be.true;
+      await expect(setFrozen(false)).to.be.rejectedWith(/UserNotFrozen/);
+    });
+
+    it("rejects non-admin callers", async () => {
+      await expect(
+        program.methods.adminFreezeUser().accounts({
+          admin: user.publicKey, config: cfgPda, userStake: stake,
+        }).signers([user]).rpc()
+      ).to.be.rejectedWith(/Unauthorized/);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3r · Admin bonus unlock
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("admin bonus unlock", () => {
+    const user  = Keypair.generate();
+    const stake = findPda([SEED_USER, user.publicKey.toBuffer()], PROGRAM_ID);
+    const grant = (pct: number, signer: Keypair = admin) =>
+      program.methods.adminUnlockBonus(pct).accounts({
+        admin: signer.publicKey, config: cfgPda, userStake: stake,
+      }).signers([signer]).rpc();
+    const summary = () =>
+      program.methods.getStakeSummary().accounts({ userStake: stake, config: cfgPda, tokenMint: mint }).view();
+
+    before("register and stake", async () => {
+      await connection.confirmTransaction(await connection.requestAirdrop(user.publicKey, LAMPORTS_PER_SOL));
+      const ata = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+      await mintTo(connection, admin, mint, ata, admin, BigInt(TOKEN_UNIT.toString()));
+      await program.methods.registerUser(new BN(0)).accounts({
+        user: user.publicKey, userStake: stake, config: cfgPda,
+        systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+      await program.methods.stake(TOKEN_UNIT, new BN(0), false).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+    });
+
+    it("raises the unlocked amount without touching principal", async () => {
+      const before = await summary();
+      await grant(30);
+      const after = await summary();
+      expect((await program.account.userStake.fetch(stake)).bonusUnlockBps).to.equal(3_000);
+      expect(after.stakedAmount.eq(before.stakedAmount)).to.be.true;
+      expect(after.unlockedAmount.sub(before.unlockedAmount).eq(TOKEN_UNIT.muln(3).divn(10))).to.be.true;
+    });
+
+    it("rejects out-of-range percentages and non-admin callers", async () => {
+      await expect(grant(0)).to.be.rejectedWith(/InvalidBonusUnlock/);
+      await expect(grant(101)).to.be.rejectedWith(/InvalidBonusUnlock/);
+      await expect(grant(10, user)).to.be.rejectedWith(/Unauthorized/);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3s · Structured transition logs
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("structured transition logs", () => {
+    const user  = Keypair.generate();
+    const stake = findPda([SEED_USER, user.publicKey.toBuffer()], PROGRAM_ID);
+    const logsOf = async (sig: string) => {
+      await connection.confirmTransaction(sig, "confirmed");
+      const tx = await connection.getTransaction(sig, { commitment: "confirmed", maxSupportedTransactionVersion: 0 });
+      return tx!.meta!.logMessages!.filter(l => l.startsWith("Program log: MCAR:v1:"));
+    };
+
+    it("logs a parseable MCAR:v1 line for stake", async () => {
+      await connection.confirmTransaction(await connection.requestAirdrop(user.publicKey, LAMPORTS_PER_SOL));
+      const ata = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+      await mintTo(connection, admin, mint, ata, admin, BigInt(TOKEN_UNIT.toString()));
+      const registerSig = await program.methods.registerUser(new BN(0)).accounts({
+        user: user.publicKey, userStake: stake, config: cfgPda,
+        systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+      expect(await logsOf(registerSig)).to.deep.equal([`Program log: MCAR:v1:register_user:user=${user.publicKey}`]);
+
+      const sig = await program.methods.stake(TOKEN_UNIT, new BN(0), false).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+      const [line] = await logsOf(sig);
+      const [, , action, ...fields] = line.replace("Program log: ", "").split(":");
+      expect(action).to.equal("stake");
+      expect(Object.fromEntries(fields.map(f => f.split("=")))).to.deep.equal({
+        user: user.publicKey.toBase58(),
+        amount: TOKEN_UNIT.toString(),
+        staked: TOKEN_UNIT.toString(),
+      });
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3t · Multiple positions per owner
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("multiple positions per owner", () => {
+    const user = Keypair.generate();
+    const positionPda = (id: number) =>
+      findPda([SEED_USER, user.publicKey.toBuffer(), ...(id === 0 ? [] : [new BN(id).toArrayLike(Buffer, "le", 8)])], PROGRAM_ID);
+    let ata: PublicKey;
+    const stakeInto = (id: number, amount: BN) =>
+      program.methods.stake(amount, new BN(0), false).accounts({
+        user: user.publicKey, userStake: positionPda(id), userTokenAccount: ata,
+        stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+
+    before("register positions 0 and 1", async () => {
+      await connection.confirmTransaction(await connection.requestAirdrop(user.publicKey, LAMPORTS_PER_SOL));
+      ata = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+      await mintTo(connection, admin, mint, ata, admin, 3n * BigInt(TOKEN_UNIT.toString()));
+      for (const id of [0, 1]) {
+        await program.methods.registerUser(new BN(id)).accounts({
+          user: user.publicKey, userStake: positionPda(id), config: cfgPda,
+          systemProgram: SystemProgram.programId,
+        }).signers([user]).rpc();
+      }
+    });
+
+    it("keeps principal and unlock clocks independent", async () => {
+      await stakeInto(0, TOKEN_UNIT);
+      const before = await program.account.userStake.fetch(positionPda(0));
+      await stakeInto(1, TOKEN_UNIT.muln(2));
+      const legacy = await program.account.userStake.fetch(positionPda(0));
+      const second = await program.account.userStake.fetch(positionPda(1));
+      expect(legacy.positionId.toNumber()).to.equal(0);
+      expect(second.positionId.toNumber()).to.equal(1);
+      expect(legacy.stakedAmount.eq(TOKEN_UNIT)).to.be.true;
+      expect(second.stakedAmount.eq(TOKEN_UNIT.muln(2))).to.be.true;
+      // Staking into position 1 did not touch position 0's clock or cycle
+      expect(legacy.startTimestamp.eq(before.startTimestamp)).to.be.true;
+      expect(legacy.lastYieldClaimTime.eq(before.lastYieldClaimTime)).to.be.true;
+    });
+
+    it("rejects a position PDA that does not match the stored id", async () => {
+      await expect(
+        program.methods.stake(TOKEN_UNIT, new BN(0), false).accounts({
+          user: user.publicKey, userStake: findPda([SEED_USER, user.publicKey.toBuffer(), Buffer.from([1])], PROGRAM_ID),
+          userTokenAccount: ata, stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+        }).signers([user]).rpc()
+      ).to.be.rejected;
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3u · Admin reflection index reset
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("admin reset user index", () => {
+    const user  = Keypair.generate();
+    const stake = findPda([SEED_USER, user.publicKey.toBuffer()], PROGRAM_ID);
+    const reset = (index: BN, signer: Keypair = admin) =>
+      program.methods.adminResetUserIndex(index).accounts({
+        admin: signer.publicKey, config: cfgPda, userStake: stake,
+      }).signers([signer]).rpc();
+
+    before("register and stake", async () => {
+      await connection.confirmTransaction(await connection.requestAirdrop(user.publicKey, LAMPORTS_PER_SOL));
+      const ata = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+      await mintTo(connection, admin, mint, ata, admin, BigInt(TOKEN_UNIT.toString()));
+      await program.methods.registerUser(new BN(0)).accounts({
+        user: user.publicKey, userStake: stake, config: cfgPda,
+        systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+      await program.methods.stake(TOKEN_UNIT, new BN(0), false).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+    });
+
+    it("rejects an index above the global one and non-admin callers", async () => {
+      const { reflectionIndex } = await program.account.globalConfig.fetch(cfgPda);
+      await expect(reset(reflectionIndex.addn(1))).to.be.rejectedWith(/InvalidReflectionIndex/);
+      await expect(reset(reflectionIndex, user)).to.be.rejectedWith(/Unauthorized/);
+    });
+
+    it("makes reflections since the corrected index claimable", async () => {
+      const { reflectionIndex } = await program.account.globalConfig.fetch(cfgPda);
+      // 1e6 index units on 1 token (1e9 base units) at scale 1e12 = 1_000 lamports
+      await reset(reflectionIndex.subn(1_000_000));
+      const pending = await program.methods.getAllPending().accounts({
+        userStake: stake, config: cfgPda, tokenMint: mint,
+      }).view();
+      expect(pending.reflectionLamports.toNumber()).to.equal(1_000);
+
+      const treasury0 = await connection.getBalance(treasuryPda);
+      await program.methods.claimReflections(false, false).accounts({
+        user: user.publicKey, userStake: stake, solTreasury: treasuryPda,
+        config: cfgPda, systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+      expect(treasury0 - await connection.getBalance(treasuryPda)).to.equal(1_000);
+      expect((await program.account.userStake.fetch(stake)).lastClaimedIndex.eq(reflectionIndex)).to.be.true;
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3v · Staking window
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("staking window", () => {
+    const user  = Keypair.generate();
+    const stake = findPda([SEED_USER, user.publicKey.toBuffer()], PROGRAM_ID);
+    let ata: PublicKey;
+    const setWindow = (start: number, end: number) =>
+      program.methods.setStakingWindow(toBN(start), toBN(end)).accounts({
+        admin: admin.publicKey, config: cfgPda,
+      }).signers([admin]).rpc();
+    const stakeTokens = () =>
+      program.methods.stake(TOKEN_UNIT, new BN(0), false).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+    const chainNow = async () => (await connection.getBlockTime(await connection.getSlot()))!;
+
+    before("register", async () => {
+      await connection.confirmTransaction(await connection.requestAirdrop(user.publicKey, LAMPORTS_PER_SOL));
+      ata = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+      await mintTo(connection, admin, mint, ata, admin, 3n * BigInt(TOKEN_UNIT.toString()));
+      await program.methods.registerUser(new BN(0)).accounts({
+        user: user.publicKey, userStake: stake, config: cfgPda,
+        systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+    });
+    after("disable the window", async () => { await setWindow(0, 0); });
+
+    it("rejects stakes before the window opens", async () => {
+      const now = await chainNow();
+      await setWindow(now + 3_600, now + 7_200);
+      await expect(stakeTokens()).to.be.rejectedWith(/StakingNotOpen/);
+    });
+
+    it("accepts stakes inside the window", async () => {
+      const now = await chainNow();
+      await setWindow(now - 3_600, now + 3_600);
+      await stakeTokens();
+      expect((await program.account.userStake.fetch(stake)).stakedAmount.eq(TOKEN_UNIT)).to.be.true;
+    });
+
+    it("rejects stakes after the window closes but still allows claims", async () => {
+      const now = await chainNow();
+      await setWindow(now - 7_200, now - 3_600);
+      await expect(stakeTokens()).to.be.rejectedWith(/StakingNotOpen/);
+      await program.methods.claimReflections(true, false).accounts({
+        user: user.publicKey, userStake: stake, solTreasury: treasuryPda,
+        config: cfgPda, systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+    });
+
+    it("rejects an inverted window", async () => {
+      await expect(setWindow(2_000, 1_000)).to.be.rejectedWith(/InvalidStakingWindow/);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3w · Tiny stake withdrawals
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("tiny stake withdrawals", () => {
+    const user  = Keypair.generate();
+    const stake = findPda([SEED_USER, user.publicKey.toBuffer()], PROGRAM_ID);
+    let ata: PublicKey;
+    const unstake = (amount: number) =>
+      program.methods.unstake(toBN(amount)).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        vaultAuthority: authPda, stakedVault, config: cfgPda, tokenMint: mint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+
+    before("stake 5 base units", async () => {
+      await connection.confirmTransaction(await connection.requestAirdrop(user.publicKey, LAMPORTS_PER_SOL));
+      ata = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+      await mintTo(connection, admin, mint, ata, admin, 5n);
+      await program.methods.registerUser(new BN(0)).accounts({
+        user: user.publicKey, userStake: stake, config: cfgPda,
+        systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+      await program.methods.stake(toBN(5), new BN(0), false).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+    });
+
+    it("fails with program errors, not a token CPI error, when nothing is unlocked", async () => {
+      const summary = await program.methods.getStakeSummary().accounts({
+        userStake: stake, config: cfgPda, tokenMint: mint,
+      }).view();
+      expect(summary.unlockedAmount.isZero()).to.be.true;
+      await expect(unstake(0)).to.be.rejectedWith(/InvalidAmount/);
+      await expect(unstake(1)).to.be.rejectedWith(/NoTokensUnlockedYet/);
+    });
+
+    it("estimates when the first yield unit becomes claimable", async () => {
+      const accounts = { userStake: stake, config: cfgPda, tokenMint: mint };
+      const pending = await program.methods.getAllPending().accounts(accounts).view();
+      const wait = (await program.methods.getTimeToNextYieldUnit().accounts(accounts).view()).toNumber();
+      const cfg = await program.account.globalConfig.fetch(cfgPda);
+      if (cfg.yieldRateBps === 0) {
+        expect(wait).to.equal(-1);
+        return;
+      }
+      expect(pending.yieldTokens.isZero()).to.be.true;
+      // ceil(10_000 * year / (principal * rate)) from the stake, minus the time already elapsed
+      const info = await program.account.userStake.fetch(stake);
+      const needed = Math.ceil(10_000 * 365 * 86_400 / (5 * cfg.yieldRateBps));
+      const elapsed = Math.floor(Date.now() / 1000) - info.lastYieldClaimTime.toNumber();
+      expect(wait).to.be.closeTo(needed - elapsed, 30);
+    });
+
+    it("reports AmountExceedsUnlocked only once something is unlocked", async () => {
+      await program.methods.adminUnlockBonus(20).accounts({ // 20% of 5 base units
+        admin: admin.publicKey, config: cfgPda, userStake: stake,
+      }).signers([admin]).rpc();
+      await expect(unstake(2)).to.be.rejectedWith(/AmountExceedsUnlocked/);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3x · Minimum stake duration for reflections
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("reflection min stake duration", () => {
+    const user  = Keypair.generate();
+    const stake = findPda([SEED_USER, user.publicKey.toBuffer()], PROGRAM_ID);
+    const setDuration = (seconds: number) =>
+      program.methods.setReflectionMinStakeDuration(toBN(seconds)).accounts({
+        admin: admin.publicKey, config: cfgPda,
+      }).signers([admin]).rpc();
+    const claim = () =>
+      program.methods.claimReflections(false, false).accounts({
+        user: user.publicKey, userStake: stake, solTreasury: treasuryPda,
+        config: cfgPda, systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+
+    before("stake and receive reflections", async () => {
+      await connection.confirmTransaction(await connection.requestAirdrop(user.publicKey, LAMPORTS_PER_SOL));
+      const ata = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+      await mintTo(connection, admin, mint, ata, admin, BigInt(TOKEN_UNIT.toString()));
+      await program.methods.registerUser(new BN(0)).accounts({
+        user: user.publicKey, userStake: stake, config: cfgPda,
+        systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+      await program.methods.stake(TOKEN_UNIT, new BN(0), false).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+
+      await provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          SystemProgram.transfer({ fromPubkey: admin.publicKey, toPubkey: treasuryPda, lamports: LAMPORTS_PER_SOL })
+        ), [admin]);
+      const supply = (await getMint(connection, mint)).supply;
+      await program.methods.depositReflectionFunds(toBN(LAMPORTS_PER_SOL), toBN(supply)).accounts({
+        admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+      }).signers([admin]).rpc();
+    });
+    after("disable the requirement", async () => { await setDuration(0); });
+
+    it("rejects a claim just before the duration has passed", async () => {
+      await setDuration(3_600);
+      await expect(claim()).to.be.rejectedWith(/StakeTooYoung/);
+    });
+
+    it("pays once the duration has passed", async () => {
+      const { startTimestamp } = await program.account.userStake.fetch(stake);
+      const now = (await connection.getBlockTime(await connection.getSlot()))!;
+      await setDuration(Math.max(now - startTimestamp.toNumber(), 0));
+      await claim();
+      expect((await program.account.userStake.fetch(stake)).unclaimedReflections.isZero()).to.be.true;
+    });
+
+    it("lets a stake paying the eligibility fee claim right away", async () => {
+      const [delayed, immediate] = [Keypair.generate(), Keypair.generate()];
+      const stakeOf = (u: Keypair) => findPda([SEED_USER, u.publicKey.toBuffer()], PROGRAM_ID);
+      const buffer = await createAccount(connection, admin, mint, admin.publicKey, Keypair.generate());
+      await program.methods.setYieldToReflection(0).accounts({
+        admin: admin.publicKey, config: cfgPda, yieldReflectionBuffer: buffer,
+      }).signers([admin]).rpc();
+      const setFee = (bps: number) =>
+        program.methods.setImmediateEligibilityFee(bps).accounts({
+          admin: admin.publicKey, config: cfgPda,
+        }).signers([admin]).rpc();
+      await expect(setFee(10_000)).to.be.rejectedWith(/InvalidImmediateEligibilityFee/);
+      await setFee(100); // 1%
+
+      for (const [u, immediateEligible] of [[delayed, false], [immediate, true]] as [Keypair, boolean][]) {
+        await connection.confirmTransaction(await connection.requestAirdrop(u.publicKey, LAMPORTS_PER_SOL));
+        const ata = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, u.publicKey)).address;
+        await mintTo(connection, admin, mint, ata, admin, BigInt(TOKEN_UNIT.toString()));
+        await program.methods.registerUser(new BN(0)).accounts({
+          user: u.publicKey, userStake: stakeOf(u), config: cfgPda,
+          systemProgram: SystemProgram.programId,
+        }).signers([u]).rpc();
+        await program.methods.stake(TOKEN_UNIT, new BN(0), immediateEligible).accounts({
+          user: u.publicKey, userStake: stakeOf(u), userTokenAccount: ata,
+          stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+          yieldReflectionBuffer: buffer,
+        }).signers([u]).rpc();
+      }
+      // Only the immediate stake paid, and only its net amount is staked
+      expect((await getAccount(connection, buffer)).amount).to.equal(BigInt(TOKEN_UNIT.divn(100).toString()));
+      expect((await program.account.userStake.fetch(stakeOf(delayed))).stakedAmount.eq(TOKEN_UNIT)).to.be.true;
+      expect((await program.account.userStake.fetch(stakeOf(immediate))).stakedAmount
+        .eq(TOKEN_UNIT.sub(TOKEN_UNIT.divn(100)))).to.be.true;
+
+      await setDuration(3_600);
+      await provider.sendAndConfirm(new anchor.web3.Transaction().add(
+        SystemProgram.transfer({ fromPubkey: admin.publicKey, toPubkey: treasuryPda, lamports: LAMPORTS_PER_SOL }),
+      ), [admin]);
+      const supply = (await getMint(connection, mint)).supply;
+      await program.methods.depositReflectionFunds(toBN(LAMPORTS_PER_SOL), toBN(supply)).accounts({
+        admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+      }).signers([admin]).rpc();
+
+      const claimOf = (u: Keypair) =>
+        program.methods.claimReflections(false, false).accounts({
+          user: u.publicKey, userStake: stakeOf(u), solTreasury: treasuryPda,
+          config: cfgPda, systemProgram: SystemProgram.programId,
+        }).signers([u]).rpc();
+      await expect(claimOf(delayed)).to.be.rejectedWith(/StakeTooYoung/);
+      await claimOf(immediate);
+      expect((await program.account.userStake.fetch(stakeOf(immediate))).lastReflectionClaimTime.gtn(0)).to.be.true;
+      await setFee(0);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3y · Claim all positions
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("claim all positions", () => {
+    const user = Keypair.generate();
+    const positionPda = (id: number) =>
+      findPda([SEED_USER, user.publicKey.toBuffer(), ...(id === 0 ? [] : [new BN(id).toArrayLike(Buffer, "le", 8)])], PROGRAM_ID);
+    const positions = [positionPda(0), positionPda(1)];
+    const claimAll = (stakes: PublicKey[]) =>
+      program.methods.claimAllPositions().accounts({
+        user: user.publicKey, solTreasury: treasuryPda,
+        config: cfgPda, systemProgram: SystemProgram.programId,
+      }).remainingAccounts(stakes.map(pubkey => ({ pubkey, isSigner: false, isWritable: true })))
+        .signers([user]).rpc();
+
+    before("stake into two positions and receive reflections", async () => {
+      await connection.confirmTransaction(await connection.requestAirdrop(user.publicKey, LAMPORTS_PER_SOL));
+      const ata = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+      await mintTo(connection, admin, mint, ata, admin, 3n * BigInt(TOKEN_UNIT.toString()));
+      for (const [id, amount] of [[0, TOKEN_UNIT], [1, TOKEN_UNIT.muln(2)]] as [number, BN][]) {
+        await program.methods.registerUser(new BN(id)).accounts({
+          user: user.publicKey, userStake: positionPda(id), config: cfgPda,
+          systemProgram: SystemProgram.programId,
+        }).signers([user]).rpc();
+        await program.methods.stake(amount, new BN(0), false).accounts({
+          user: user.publicKey, userStake: positionPda(id), userTokenAccount: ata,
+          stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+        }).signers([user]).rpc();
+      }
+
+      await provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          SystemProgram.transfer({ fromPubkey: admin.publicKey, toPubkey: treasuryPda, lamports: LAMPORTS_PER_SOL })
+        ), [admin]);
+      const supply = (await getMint(connection, mint)).supply;
+      await program.methods.depositReflectionFunds(toBN(LAMPORTS_PER_SOL), toBN(supply)).accounts({
+        admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+      }).signers([admin]).rpc();
+    });
+
+    it("rejects a position owned by someone else", async () => {
+      await expect(claimAll([positions[0], pubStake])).to.be.rejectedWith(/UserAccountMismatch/);
+    });
+
+    it("rejects the same position twice", async () => {
+      await expect(claimAll([positions[0], positions[0]])).to.be.rejectedWith(/InvalidPositionAccounts/);
+    });
+
+    it("pays the sum of the individual claims in one transfer", async () => {
+      let expected = 0;
+      for (const stake of positions) {
+        const pending = await program.methods.getAllPending().accounts({
+          userStake: stake, config: cfgPda, tokenMint: mint,
+        }).view();
+        expect(pending.reflectionLamports.toNumber()).to.be.greaterThan(0);
+        expected += pending.reflectionLamports.toNumber();
+      }
+      const balance0 = await connection.getBalance(user.publicKey);
+
+      await claimAll(positions);
+
+      expect(await connection.getBalance(user.publicKey) - balance0).to.equal(expected);
+      for (const stake of positions) {
+        expect((await program.account.userStake.fetch(stake)).unclaimedReflections.isZero()).to.be.true;
+      }
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3z · Emissions start
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("emissions start", () => {
+    const user  = Keypair.generate();
+    const stake = findPda([SEED_USER, user.publicKey.toBuffer()], PROGRAM_ID);
+    const setEmissionsStart = (timestamp: number) =>
+      program.methods.setEmissionsStart(toBN(timestamp)).accounts({
+        admin: admin.publicKey, config: cfgPda,
+      }).signers([admin]).rpc();
+
+    after("start emissions immediately again", async () => { await setEmissionsStart(0); });
+
+    it("accrues no yield for a user registered before emissions start", async () => {
+      const now = (await connection.getBlockTime(await connection.getSlot()))!;
+      const emissionsStart = now + 3_600;
+      await setEmissionsStart(emissionsStart);
+
+      await connection.confirmTransaction(await connection.requestAirdrop(user.publicKey, LAMPORTS_PER_SOL));
+      const ata = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+      await mintTo(connection, admin, mint, ata, admin, BigInt(TOKEN_UNIT.toString()));
+      await program.methods.registerUser(new BN(0)).accounts({
+        user: user.publicKey, userStake: stake, config: cfgPda,
+        systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+      expect((await program.account.userStake.fetch(stake)).lastYieldClaimTime.toNumber()).to.equal(emissionsStart);
+
+      await program.methods.stake(TOKEN_UNIT, new BN(0), false).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+      await new Promise(resolve => setTimeout(resolve, 2_000));
+      const pending = await program.methods.getAllPending().accounts({
+        userStake: stake, config: cfgPda, tokenMint: mint,
+      }).view();
+      expect(pending.yieldTokens.isZero()).to.be.true;
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3za · Stake with a delegate permit
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("stake with permit", () => {
+    const user    = Keypair.generate();
+    const relayer = Keypair.generate();
+    const stake   = findPda([SEED_USER, user.publicKey.toBuffer()], PROGRAM_ID);
+    let ata: PublicKey;
+    // Signed by the user: position, amount, min credited and nonce, little-endian
+    const permit = (amount: BN, nonce: number, signer = user) =>
+      Ed25519Program.createInstructionWithPrivateKey({
+        privateKey: signer.secretKey,
+        message: Buffer.concat([
+          stake.toBuffer(), amount.toArrayLike(Buffer, "le", 8),
+          new BN(0).toArrayLike(Buffer, "le", 8), new BN(nonce).toArrayLike(Buffer, "le", 8),
+        ]),
+      });
+    const stakeWithPermit = (amount: BN, nonce = 0, signer = user) =>
+      program.methods.stakeWithPermit(amount, new BN(0)).accounts({
+        relayer: relayer.publicKey, userStake: stake, userTokenAccount: ata, stakedVault,
+        vaultAuthority: authPda, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+        instructionsSysvar: SYSVAR_INSTRUCTIONS_PUBKEY,
+      }).preInstructions([permit(amount, nonce, signer)]).signers([relayer]).rpc();
+
+    before("register and approve the vault authority", async () => {
+      for (const kp of [user, relayer]) {
+        await connection.confirmTransaction(await connection.requestAirdrop(kp.publicKey, LAMPORTS_PER_SOL));
+      }
+      ata = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+      await mintTo(connection, admin, mint, ata, admin, 2n * BigInt(TOKEN_UNIT.toString()));
+      await program.methods.registerUser(new BN(0)).accounts({
+        user: user.publicKey, userStake: stake, config: cfgPda,
+        systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+      await approve(connection, user, ata, authPda, user, BigInt(TOKEN_UNIT.toString()));
+    });
+
+    it("rejects a stake above the delegated amount", async () => {
+      await expect(stakeWithPermit(TOKEN_UNIT.muln(2))).to.be.rejectedWith(/PermitAmountExceeded/);
+    });
+
+    it("rejects a permit not signed by the user", async () => {
+      await expect(stakeWithPermit(TOKEN_UNIT, 0, relayer)).to.be.rejectedWith(/PermitSignatureMissing/);
+    });
+
+    it("stakes the delegated tokens for the user", async () => {
+      const vault0 = (await getAccount(connection, stakedVault)).amount;
+      await stakeWithPermit(TOKEN_UNIT);
+      expect((await getAccount(connection, stakedVault)).amount - vault0).to.equal(BigInt(TOKEN_UNIT.toString()));
+      const info = await program.account.userStake.fetch(stake);
+      expect(info.stakedAmount.eq(TOKEN_UNIT)).to.be.true;
+      // The permit is spent
+      expect((await getAccount(connection, ata)).delegatedAmount).to.equal(0n);
+      expect(info.permitNonce.toNumber()).to.equal(1);
+    });
+
+    it("rejects a replayed permit", async () => {
+      await approve(connection, user, ata, authPda, user, BigInt(TOKEN_UNIT.toString()));
+      await expect(stakeWithPermit(TOKEN_UNIT, 0)).to.be.rejectedWith(/PermitSignatureMissing/);
+      await revoke(connection, user, ata, user);
+    });
+
+    it("rejects a revoked delegate", async () => {
+      await approve(connection, user, ata, authPda, user, BigInt(TOKEN_UNIT.toString()));
+      await revoke(connection, user, ata, user);
+      await expect(stakeWithPermit(TOKEN_UNIT, 1)).to.be.rejectedWith(/PermitDelegateMismatch/);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3zb · Compliance freeze
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("compliance freeze", () => {
+    const user  = Keypair.generate();
+    const stake = findPda([SEED_USER, user.publicKey.toBuffer()], PROGRAM_ID);
+    let ata: PublicKey;
+    const setFrozen = (frozen: boolean, signer: Keypair = admin) =>
+      program.methods.adminSetUserFrozen(frozen).accounts({
+        admin: signer.publicKey, config: cfgPda, userStake: stake,
+      }).signers([signer]).rpc();
+    const userActions = () => [
+      program.methods.stake(TOKEN_UNIT, new BN(0), false).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]),
+      program.methods.unstake(toBN(1)).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        vaultAuthority: authPda, stakedVault, config: cfgPda, tokenMint: mint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]),
+      program.methods.claimYield(true).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        rewardVault, vaultAuthority: authPda, config: cfgPda, yieldMint: mint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]),
+      program.methods.claimReflections(true, false).accounts({
+        user: user.publicKey, userStake: stake, solTreasury: treasuryPda,
+        config: cfgPda, systemProgram: SystemProgram.programId,
+      }).signers([user]),
+      program.methods.claimReflectionsFor().accounts({
+        caller: admin.publicKey, owner: user.publicKey, userStake: stake, solTreasury: treasuryPda,
+        config: cfgPda, systemProgram: SystemProgram.programId,
+      }).signers([admin]),
+    ];
+
+    before("register and stake", async () => {
+      await connection.confirmTransaction(await connection.requestAirdrop(user.publicKey, LAMPORTS_PER_SOL));
+      ata = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+      await mintTo(connection, admin, mint, ata, admin, 2n * BigInt(TOKEN_UNIT.toString()));
+      await program.methods.registerUser(new BN(0)).accounts({
+        user: user.publicKey, userStake: stake, config: cfgPda,
+        systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+      await userActions()[0].rpc();
+    });
+
+    it("rejects non-admin callers", async () => {
+      await expect(setFrozen(true, user)).to.be.rejectedWith(/Unauthorized/);
+    });
+
+    it("blocks staking, unstaking and every claim while frozen", async () => {
+      await setFrozen(true);
+      expect((await program.account.userStake.fetch(stake)).complianceFrozen).to.be.true;
+      for (const action of userActions()) {
+        await expect(action.rpc()).to.be.rejectedWith(/UserComplianceFrozen/);
+      }
+    });
+
+    it("restores access after unfreezing", async () => {
+      await setFrozen(false);
+      expect((await program.account.userStake.fetch(stake)).complianceFrozen).to.be.false;
+      await userActions()[0].rpc();
+      await userActions()[3].rpc(); // Dry-run reflection claim
+      expect((await program.account.userStake.fetch(stake)).stakedAmount.eq(TOKEN_UNIT.muln(2))).to.be.true;
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3zc · Reflection burn treasury
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("reflection burn treasury", () => {
+    const burnTreasury = findPda([Buffer.from("burn_treasury")], PROGRAM_ID);
+    const setBurnBps = (bps: number) =>
+      program.methods.setReflectionBurnBps(bps).accounts({
+        admin: admin.publicKey, config: cfgPda, burnTreasury, systemProgram: SystemProgram.programId,
+      }).signers([admin]).rpc();
+
+    before("divert 10% of deposits", async () => {
+      await expect(setBurnBps(10_001)).to.be.rejectedWith(/InvalidReflectionBurnBps/);
+      await setBurnBps(1_000);
+    });
+    after("disable the burn", async () => { await setBurnBps(0); });
+
+    it("moves the burn share out and indexes only the remainder", async () => {
+      const DEPOSIT = LAMPORTS_PER_SOL;
+      await provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          SystemProgram.transfer({ fromPubkey: admin.publicKey, toPubkey: treasuryPda, lamports: DEPOSIT })
+        ), [admin]);
+      const cfg0   = await program.account.globalConfig.fetch(cfgPda);
+      const burn0  = await connection.getBalance(burnTreasury);
+      const supply = (await getMint(connection, mint)).supply;
+
+      await program.methods.depositReflectionFunds(toBN(DEPOSIT), toBN(supply)).accounts({
+        admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+        burnTreasury, systemProgram: SystemProgram.programId,
+      }).signers([admin]).rpc();
+
+      expect(await connection.getBalance(burnTreasury) - burn0).to.equal(DEPOSIT / 10);
+      const cfg1 = await program.account.globalConfig.fetch(cfgPda);
+      const expected = toBN(DEPOSIT - DEPOSIT / 10).mul(toBN(1_000_000_000_000)).div(toBN(supply));
+      expect(cfg1.reflectionIndex.sub(cfg0.reflectionIndex).eq(expected)).to.be.true;
+    });
+
+    it("rejects a deposit without the burn treasury", async () => {
+      const supply = (await getMint(connection, mint)).supply;
+      await expect(
+        program.methods.depositReflectionFunds(toBN(1_000), toBN(supply)).accounts({
+          admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+        }).signers([admin]).rpc()
+      ).to.be.rejectedWith(/BurnTreasuryMissing/);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3zd · Yield routed into reflections
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("yield to reflection buffer", () => {
+    const user  = Keypair.generate();
+    const stake = findPda([SEED_USER, user.publicKey.toBuffer()], PROGRAM_ID);
+    let ata: PublicKey, buffer: PublicKey;
+    const setYieldToReflection = (bps: number) =>
+      program.methods.setYieldToReflection(bps).accounts({
+        admin: admin.publicKey, config: cfgPda, yieldReflectionBuffer: buffer,
+      }).signers([admin]).rpc();
+    const claimYield = (withBuffer: boolean) =>
+      program.methods.claimYield(false).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        rewardVault, vaultAuthority: authPda, config: cfgPda, yieldMint: mint,
+        tokenProgram: TOKEN_PROGRAM_ID, ...(withBuffer ? { yieldReflectionBuffer: buffer } : {}),
+      }).signers([user]).rpc();
+
+    before("stake and route 20% of yield", async () => {
+      await connection.confirmTransaction(await connection.requestAirdrop(user.publicKey, LAMPORTS_PER_SOL));
+      ata    = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+      buffer = await createAccount(connection, admin, mint, admin.publicKey, Keypair.generate());
+      await mintTo(connection, admin, mint, ata, admin, 100n * BigInt(TOKEN_UNIT.toString()));
+      await program.methods.registerUser(new BN(0)).accounts({
+        user: user.publicKey, userStake: stake, config: cfgPda,
+        systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+      await program.methods.stake(TOKEN_UNIT.muln(100), new BN(0), false).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+
+      await expect(setYieldToReflection(10_001)).to.be.rejectedWith(/InvalidYieldToReflectionBps/);
+      await setYieldToReflection(2_000);
+      await new Promise(resolve => setTimeout(resolve, 2_000));
+    });
+    after("pay all yield to stakers again", async () => { await setYieldToReflection(0); });
+
+    it("requires the buffer account", async () => {
+      await expect(claimYield(false)).to.be.rejectedWith(/YieldReflectionBufferMissing/);
+    });
+
+    it("moves the earmarked fraction of a claim to the buffer", async () => {
+      const cfg0    = await program.account.globalConfig.fetch(cfgPda);
+      const user0   = (await getAccount(connection, ata)).amount;
+      const buffer0 = (await getAccount(connection, buffer)).amount;
+
+      await claimYield(true);
+
+      const received  = (await getAccount(connection, ata)).amount - user0;
+      const earmarked = (await getAccount(connection, buffer)).amount - buffer0;
+      expect(received > 0n).to.be.true;
+      expect(earmarked).to.equal((received + earmarked) * 2_000n / 10_000n);
+      const cfg1 = await program.account.globalConfig.fetch(cfgPda);
+      expect(cfg1.yieldEarmarkedForReflections.sub(cfg0.yieldEarmarkedForReflections).toString())
+        .to.equal(earmarked.toString());
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3ze · Last action audit trail
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("last action audit trail", () => {
+    const user  = Keypair.generate();
+    const stake = findPda([SEED_USER, user.publicKey.toBuffer()], PROGRAM_ID);
+    let ata: PublicKey;
+    const lastAction = async () => {
+      const s = await program.account.userStake.fetch(stake);
+      return { code: s.lastAction, at: s.lastActionTimestamp.toNumber() };
+    };
+
+    before("fund the user", async () => {
+      await connection.confirmTransaction(await connection.requestAirdrop(user.publicKey, LAMPORTS_PER_SOL));
+      ata = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+      await mintTo(connection, admin, mint, ata, admin, BigInt(TOKEN_UNIT.toString()));
+    });
+
+    it("records register, stake, accrue and claim codes", async () => {
+      await program.methods.registerUser(new BN(0)).accounts({
+        user: user.publicKey, userStake: stake, config: cfgPda,
+        systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+      const registered = await lastAction();
+      expect(registered.code).to.equal(5);
+      expect(registered.at).to.be.greaterThan(0);
+
+      await program.methods.stake(TOKEN_UNIT, new BN(0), false).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+      expect((await lastAction()).code).to.equal(1);
+
+      await program.methods.accrueReflections().accounts({
+        user: user.publicKey, userStake: stake, config: cfgPda,
+      }).signers([user]).rpc();
+      expect((await lastAction()).code).to.equal(10);
+
+      await program.methods.claimReflections(false, false).accounts({
+        user: user.publicKey, userStake: stake, solTreasury: treasuryPda,
+        config: cfgPda, systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+      expect((await lastAction()).code).to.equal(4);
+    });
+
+    it("leaves the trail untouched on a dry run", async () => {
+      const before = await lastAction();
+      await program.methods.claimYield(true).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        rewardVault, vaultAuthority: authPda, config: cfgPda, yieldMint: mint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+      expect(await lastAction()).to.deep.equal(before);
+    });
+
+    it("records admin actions and leaves current accounts alone on migration", async () => {
+      await program.methods.adminSetUserFrozen(false).accounts({
+        admin: admin.publicKey, config: cfgPda, userStake: stake,
+      }).signers([admin]).rpc();
+      expect((await lastAction()).code).to.equal(11);
+
+      const size = (await connection.getAccountInfo(stake))!.data.length;
+      await program.methods.migrateUserStake().accounts({
+        payer: admin.publicKey, userStake: stake, systemProgram: SystemProgram.programId,
+      }).signers([admin]).rpc();
+      expect((await connection.getAccountInfo(stake))!.data.length).to.equal(size);
+      await expect(program.methods.migrateUserStake().accounts({
+        payer: admin.publicKey, userStake: cfgPda, systemProgram: SystemProgram.programId,
+      }).signers([admin]).rpc()).to.be.rejectedWith(/InvalidUserStakeAccount/);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3zf · Stake credit guard
+  // ──────────────────────────────────────────────────────────────────────────
+  // The suite's mint has no transfer fee, so the full amount is credited; fee
+  // mints at acceptable and excessive rates are covered by the program's unit tests.
+  describe("stake credit guard", () => {
+    const user  = Keypair.generate();
+    const stake = findPda([SEED_USER, user.publicKey.toBuffer()], PROGRAM_ID);
+    let ata: PublicKey;
+    const stakeWithGuard = (minCredited: BN) =>
+      program.methods.stake(TOKEN_UNIT, minCredited, false).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+
+    before("register", async () => {
+      await connection.confirmTransaction(await connection.requestAirdrop(user.publicKey, LAMPORTS_PER_SOL));
+      ata = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+      await mintTo(connection, admin, mint, ata, admin, BigInt(TOKEN_UNIT.toString()));
+      await program.methods.registerUser(new BN(0)).accounts({
+        user: user.publicKey, userStake: stake, config: cfgPda,
+        systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+    });
+
+    it("reverts when less than the minimum would be credited", async () => {
+      await expect(stakeWithGuard(TOKEN_UNIT.addn(1))).to.be.rejectedWith(/SlippageExceeded/);
+      expect((await program.account.userStake.fetch(stake)).stakedAmount.toNumber()).to.equal(0);
+    });
+
+    it("credits the full amount when the minimum is met", async () => {
+      await stakeWithGuard(TOKEN_UNIT);
+      expect((await program.account.userStake.fetch(stake)).stakedAmount.eq(TOKEN_UNIT)).to.be.true;
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3zg · Reflection index rebase
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("reflection index rebase", () => {
+    const SCALE = new BN(1_000_000_000_000);
+    const rebase = (oldSupply: BN, newSupply: BN, deposit: BN) =>
+      program.methods.adminRebaseReflectionIndex(oldSupply, newSupply, deposit).accounts({
+        admin: admin.publicKey, config: cfgPda,
+      }).signers([admin]).rpc();
+    const setPaused = (paused: boolean) =>
+      program.methods.setPaused(paused).accounts({
+        admin: admin.publicKey, config: cfgPda,
+      }).signers([admin]).rpc();
+    const index = async () => (await program.account.globalConfig.fetch(cfgPda)).reflectionIndex;
+    const small = new BN(1_000_000_000_000);
+    const large = new BN(2_000_000_000_000);
+    const deposit = new BN(1_000);
+
+    it("requires the program to be paused", async () => {
+      await expect(rebase(large, small, deposit)).to.be.rejectedWith(/NotPaused/);
+    });
+
+    it("recomputes a deposit indexed over the wrong supply, then restores it", async () => {
+      await setPaused(true);
+      try {
+        const before = await index();
+        // The deposit was indexed over twice the real supply: add back the missing half
+        await rebase(large, small, deposit);
+        const healed = await index();
+        const wrong = deposit.mul(SCALE).div(large);
+        const right = deposit.mul(SCALE).div(small);
+        expect(healed.eq(before.sub(wrong).add(right))).to.be.true;
+
+        // Undo it so later sections see the original index
+        await rebase(small, large, deposit);
+        expect((await index()).eq(before)).to.be.true;
+        await expect(rebase(small, new BN(0), deposit)).to.be.rejectedWith(/InvalidIndexRebase/);
+      } finally {
+        await setPaused(false);
+      }
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3zh · Wrapped SOL reflection claims
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("wrapped SOL reflection claims", () => {
+    const user  = Keypair.generate();
+    const stake = findPda([SEED_USER, user.publicKey.toBuffer()], PROGRAM_ID);
+    const wsolAta = getAssociatedTokenAddressSync(NATIVE_MINT, user.publicKey);
+    const claimWrapped = () =>
+      program.methods.claimReflectionsWrapped().accounts({
+        user: user.publicKey, userStake: stake, userWsolAccount: wsolAta,
+        solTreasury: treasuryPda, config: cfgPda, tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+
+    before("stake and deposit reflections", async () => {
+      await connection.confirmTransaction(await connection.requestAirdrop(user.publicKey, LAMPORTS_PER_SOL));
+      const ata = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+      await mintTo(connection, admin, mint, ata, admin, BigInt(TOKEN_UNIT.toString()));
+      await program.methods.registerUser(new BN(0)).accounts({
+        user: user.publicKey, userStake: stake, config: cfgPda,
+        systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+      await program.methods.stake(TOKEN_UNIT, new BN(0), false).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+
+      await provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          SystemProgram.transfer({ fromPubkey: admin.publicKey, toPubkey: treasuryPda, lamports: LAMPORTS_PER_SOL })
+        ), [admin]);
+      const supply = (await getMint(connection, mint)).supply;
+      await program.methods.depositReflectionFunds(toBN(LAMPORTS_PER_SOL), toBN(supply)).accounts({
+        admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+      }).signers([admin]).rpc();
+    });
+
+    it("asks for the wSOL account to be created first", async () => {
+      await expect(claimWrapped()).to.be.rejectedWith(/WrappedSolAccountMissing/);
+    });
+
+    it("pays reflections into the wSOL account with a synced balance", async () => {
+      await getOrCreateAssociatedTokenAccount(connection, admin, NATIVE_MINT, user.publicKey);
+      const pending = (await program.methods.getAllPending().accounts({
+        userStake: stake, config: cfgPda, tokenMint: mint,
+      }).view()).reflectionLamports;
+      expect(pending.toNumber()).to.be.greaterThan(0);
+
+      await claimWrapped();
+      expect((await getAccount(connection, wsolAta)).amount).to.equal(BigInt(pending.toString()));
+      expect((await program.account.userStake.fetch(stake)).lastAction).to.equal(4);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3zi · Bonus yield grants
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("bonus yield grants", () => {
+    const user  = Keypair.generate();
+    const stake = findPda([SEED_USER, user.publicKey.toBuffer()], PROGRAM_ID);
+    const BONUS = TOKEN_UNIT.muln(3);
+    let ata: PublicKey;
+    const grant = (amount: BN, signer: Keypair = admin) =>
+      program.methods.adminGrantYield(amount).accounts({
+        admin: signer.publicKey, config: cfgPda, userStake: stake, rewardVault,
+      }).signers([signer]).rpc();
+
+    before("register and stake", async () => {
+      await connection.confirmTransaction(await connection.requestAirdrop(user.publicKey, LAMPORTS_PER_SOL));
+      ata = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+      await mintTo(connection, admin, mint, ata, admin, BigInt(TOKEN_UNIT.toString()));
+      await program.methods.registerUser(new BN(0)).accounts({
+        user: user.publicKey, userStake: stake, config: cfgPda,
+        systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+      await program.methods.stake(TOKEN_UNIT, new BN(0), false).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+    });
+
+    it("is admin-only and must be backed by the reward vault", async () => {
+      await expect(grant(BONUS, user)).to.be.rejectedWith(/Unauthorized/);
+      const vault = (await getAccount(connection, rewardVault)).amount;
+      await expect(grant(toBN(vault + 1n))).to.be.rejectedWith(/InsufficientRewardVault/);
+    });
+
+    it("credits unclaimed yield the user can claim", async () => {
+      await grant(BONUS);
+      expect((await program.account.userStake.fetch(stake)).unclaimedYield.gte(BONUS)).to.be.true;
+
+      const before = (await getAccount(connection, ata)).amount;
+      await program.methods.claimYield(false).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        rewardVault, vaultAuthority: authPda, config: cfgPda, yieldMint: mint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+      const received = (await getAccount(connection, ata)).amount - before;
+      expect(received >= BigInt(BONUS.toString())).to.be.true;
+      expect((await program.account.userStake.fetch(stake)).unclaimedYield.isZero()).to.be.true;
+    });
+
+    it("claws back part of an erroneous grant (admin-only)", async () => {
+      const clawback = (amount: BN, signer: Keypair = admin) =>
+        program.methods.adminClawbackYield(amount).accounts({
+          admin: signer.publicKey, config: cfgPda, userStake: stake,
+        }).signers([signer]).rpc();
+      await grant(BONUS);
+      await expect(clawback(TOKEN_UNIT, user)).to.be.rejectedWith(/Unauthorized/);
+
+      await clawback(TOKEN_UNIT);
+      const left = (await program.account.userStake.fetch(stake)).unclaimedYield;
+      expect(left.lt(BONUS)).to.be.true;
+      expect(left.gte(BONUS.sub(TOKEN_UNIT))).to.be.true;
+    });
+
+    it("saturates the clawback at zero", async () => {
+      await program.methods.adminClawbackYield(BONUS.muln(10)).accounts({
+        admin: admin.publicKey, config: cfgPda, userStake: stake,
+      }).signers([admin]).rpc();
+      expect((await program.account.userStake.fetch(stake)).unclaimedYield.isZero()).to.be.true;
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3zj · Legacy UserStake migration
+  // ──────────────────────────────────────────────────────────────────────────
+  // tests/fixtures/legacy-user-stake.json is a 148-byte UserStake loaded by Anchor.toml
+  describe("legacy UserStake migration", () => {
+    const legacy = new PublicKey("6RqMGnNHRtWYBcpcsQdAstNFYWdLYFw54sLTCU7UsHVS");
+    const migrate = (payer: Keypair) =>
+      program.methods.migrateUserStake().accounts({
+        payer: payer.publicKey, userStake: legacy, systemProgram: SystemProgram.programId,
+      }).signers([payer]).rpc();
+
+    it("rejects a payer that can't cover the rent delta", async () => {
+      const poor = Keypair.generate();
+      const reserve = await connection.getMinimumBalanceForRentExemption(0);
+      await provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          SystemProgram.transfer({ fromPubkey: admin.publicKey, toPubkey: poor.publicKey, lamports: reserve })
+        ), [admin]);
+      await expect(migrate(poor)).to.be.rejectedWith(/InsufficientMigrationRent/);
+      expect((await connection.getAccountInfo(legacy))!.data.length).to.equal(148);
+    });
+
+    it("grows the account and keeps it rent-exempt", async () => {
+      await migrate(admin);
+      const info = (await connection.getAccountInfo(legacy))!;
+      expect(info.data.length).to.equal(200);
+      expect(info.lamports).to.be.at.least(await connection.getMinimumBalanceForRentExemption(200));
+      const migrated = await program.account.userStake.fetch(legacy);
+      expect(migrated.stakedAmount.eq(TOKEN_UNIT)).to.be.true;
+      expect(migrated.lastAction).to.equal(0);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3zk · Batch size limit
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("batch size limit", () => {
+    const MAX_BATCH_SIZE = 8;
+    const user = Keypair.generate();
+    const positionPda = (id: number) =>
+      findPda([SEED_USER, user.publicKey.toBuffer(), ...(id === 0 ? [] : [new BN(id).toArrayLike(Buffer, "le", 8)])], PROGRAM_ID);
+    const positions = [...Array(MAX_BATCH_SIZE).keys()].map(positionPda);
+    const claimAll = (stakes: PublicKey[]) =>
+      program.methods.claimAllPositions().accounts({
+        user: user.publicKey, solTreasury: treasuryPda,
+        config: cfgPda, systemProgram: SystemProgram.programId,
+      }).remainingAccounts(stakes.map(pubkey => ({ pubkey, isSigner: false, isWritable: true })))
+        .signers([user]).rpc();
+
+    before("open the maximum number of positions and receive reflections", async () => {
+      await connection.confirmTransaction(await connection.requestAirdrop(user.publicKey, LAMPORTS_PER_SOL));
+      const ata = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+      await mintTo(connection, admin, mint, ata, admin, BigInt(TOKEN_UNIT.toString()));
+      for (const [id, stake] of positions.entries()) {
+        await program.methods.registerUser(new BN(id)).accounts({
+          user: user.publicKey, userStake: stake, config: cfgPda,
+          systemProgram: SystemProgram.programId,
+        }).signers([user]).rpc();
+      }
+      await program.methods.stake(TOKEN_UNIT, new BN(0), false).accounts({
+        user: user.publicKey, userStake: positions[0], userTokenAccount: ata,
+        stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+
+      await provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          SystemProgram.transfer({ fromPubkey: admin.publicKey, toPubkey: treasuryPda, lamports: LAMPORTS_PER_SOL })
+        ), [admin]);
+      const supply = (await getMint(connection, mint)).supply;
+      await program.methods.depositReflectionFunds(toBN(LAMPORTS_PER_SOL), toBN(supply)).accounts({
+        admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+      }).signers([admin]).rpc();
+    });
+
+    it("rejects batches over the limit", async () => {
+      await expect(claimAll([...positions, positionPda(MAX_BATCH_SIZE)])).to.be.rejectedWith(/BatchTooLarge/);
+      const pairs = [...positions, positionPda(MAX_BATCH_SIZE)].flatMap(pubkey => [
+        { pubkey, isSigner: false, isWritable: true },
+        { pubkey: user.publicKey, isSigner: false, isWritable: false },
+      ]);
+      await expect(
+        program.methods.claimReflectionsPooled().accounts({
+          caller: admin.publicKey, pool: admin.publicKey, solTreasury: treasuryPda,
+          config: cfgPda, systemProgram: SystemProgram.programId,
+        }).remainingAccounts(pairs).signers([admin]).rpc()
+      ).to.be.rejectedWith(/BatchTooLarge/);
+    });
+
+    it("processes a batch at the limit", async () => {
+      const balance0 = await connection.getBalance(user.publicKey);
+      await claimAll(positions);
+      expect(await connection.getBalance(user.publicKey)).to.be.greaterThan(balance0);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3zl · Auto-claim reflections on unstake
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("auto-claim reflections on unstake", () => {
+    const user  = Keypair.generate();
+    const stake = findPda([SEED_USER, user.publicKey.toBuffer()], PROGRAM_ID);
+    let ata: PublicKey;
+    const setAutoClaim = (enabled: boolean) =>
+      program.methods.setAutoClaimReflectionsOnUnstake(enabled).accounts({
+        admin: admin.publicKey, config: cfgPda,
+      }).signers([admin]).rpc();
+    const unstake = (withTreasury: boolean) =>
+      program.methods.unstake(toBN(1_000)).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        vaultAuthority: authPda, stakedVault, config: cfgPda, tokenMint: mint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        ...(withTreasury ? { solTreasury: treasuryPda, systemProgram: SystemProgram.programId } : {}),
+      }).signers([user]).rpc();
+    const deposit = async () => {
+      await provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          SystemProgram.transfer({ fromPubkey: admin.publicKey, toPubkey: treasuryPda, lamports: LAMPORTS_PER_SOL })
+        ), [admin]);
+      const supply = (await getMint(connection, mint)).supply;
+      await program.methods.depositReflectionFunds(toBN(LAMPORTS_PER_SOL), toBN(supply)).accounts({
+        admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+      }).signers([admin]).rpc();
+    };
+    const pendingReflections = async () =>
+      (await program.methods.getAllPending().accounts({
+        userStake: stake, config: cfgPda, tokenMint: mint,
+      }).view()).reflectionLamports.toNumber();
+
+    before("stake, unlock everything and receive reflections", async () => {
+      await connection.confirmTransaction(await connection.requestAirdrop(user.publicKey, LAMPORTS_PER_SOL));
+      ata = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+      await mintTo(connection, admin, mint, ata, admin, BigInt(TOKEN_UNIT.toString()));
+      await program.methods.registerUser(new BN(0)).accounts({
+        user: user.publicKey, userStake: stake, config: cfgPda,
+        systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+      await program.methods.stake(TOKEN_UNIT, new BN(0), false).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+      await program.methods.adminUnlockBonus(100).accounts({
+        admin: admin.publicKey, config: cfgPda, userStake: stake,
+      }).signers([admin]).rpc();
+      await deposit();
+    });
+    after("disable auto-claim", async () => { await setAutoClaim(false); });
+
+    it("leaves reflections settled but unpaid when off", async () => {
+      const pending = await pendingReflections();
+      expect(pending).to.be.greaterThan(0);
+      const balance0 = await connection.getBalance(user.publicKey);
+      await unstake(false);
+      expect(await connection.getBalance(user.publicKey)).to.equal(balance0);
+      expect(await pendingReflections()).to.equal(pending);
+    });
+
+    it("pays pending reflections during unstake when on", async () => {
+      await setAutoClaim(true);
+      await expect(unstake(false)).to.be.rejectedWith(/AutoClaimAccountsMissing/);
+
+      await deposit();
+      const pending = await pendingReflections();
+      const balance0 = await connection.getBalance(user.publicKey);
+      await unstake(true);
+      expect(await connection.getBalance(user.publicKey) - balance0).to.equal(pending);
+      expect(await pendingReflections()).to.equal(0);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3zm · Liability sum
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("liability sum", () => {
+    const liabilitySum = findPda([Buffer.from("liability_sum")], PROGRAM_ID);
+    const sum = (stakes: PublicKey[]) =>
+      program.methods.sumLiabilities().accounts({ liabilitySum, config: cfgPda })
+        .remainingAccounts(stakes.map(pubkey => ({ pubkey, isSigner: false, isWritable: false })))
+        .rpc();
+
+    it("accumulates two batches into the PDA", async () => {
+      const stakes = (await program.account.userStake.all())
+        .map(a => a.publicKey)
+        .sort((a, b) => Buffer.compare(a.toBuffer(), b.toBuffer()))
+        .slice(0, 4);
+      let expectedYield = new BN(0);
+      let expectedReflections = new BN(0);
+      for (const stake of stakes) {
+        expectedYield = expectedYield.add((await program.account.userStake.fetch(stake)).unclaimedYield);
+        const pending = await program.methods.getAllPending().accounts({
+          userStake: stake, config: cfgPda, tokenMint: mint,
+        }).view();
+        expectedReflections = expectedReflections.add(pending.reflectionLamports);
+      }
+
+      await program.methods.resetLiabilitySum().accounts({
+        admin: admin.publicKey, config: cfgPda, liabilitySum, systemProgram: SystemProgram.programId,
+      }).signers([admin]).rpc();
+      await sum(stakes.slice(0, 2));
+      await expect(sum(stakes.slice(1, 3))).to.be.rejectedWith(/LiabilityCursorOutOfOrder/);
+      await sum(stakes.slice(2));
+
+      const totals = await program.account.liabilitySum.fetch(liabilitySum);
+      expect(totals.positionsCounted.toNumber()).to.equal(stakes.length);
+      expect(totals.totalUnclaimedYield.eq(expectedYield)).to.be.true;
+      expect(totals.totalPendingReflections.eq(expectedReflections)).to.be.true;
+      expect(totals.cursor.equals(stakes[stakes.length - 1])).to.be.true;
+    });
+
+    it("rejects an oversized batch before counting anything", async () => {
+      const before = await program.account.liabilitySum.fetch(liabilitySum);
+      const stakes = (await program.account.userStake.all())
+        .map(a => a.publicKey)
+        .sort((a, b) => Buffer.compare(a.toBuffer(), b.toBuffer()))
+        .slice(-9); // MAX_BATCH_SIZE + 1, all after the cursor
+      expect(stakes.length).to.equal(9);
+      await expect(sum(stakes)).to.be.rejectedWith(/BatchTooLarge/);
+      const after = await program.account.liabilitySum.fetch(liabilitySum);
+      expect(after.positionsCounted.eq(before.positionsCounted)).to.be.true;
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3zn · Initialize vault mint checks
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("initialize vault mint checks", () => {
+    let otherVault: PublicKey;
+    const initialize = (vaults: { stakedVault: PublicKey; rewardVault: PublicKey }, tokenMint = mint) =>
+      program.methods.initialize(500, 0).accounts({
+        admin: admin.publicKey, config: cfgPda, vaultAuthority: authPda, solTreasury: treasuryPda,
+        tokenMint, ...vaults, tokenProgram: TOKEN_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+      }).signers([admin]).rpc();
+
+    before("create a vault of another mint", async () => {
+      const otherMint = await createMint(connection, admin, admin.publicKey, null, DECIMALS + 1);
+      otherVault = (await getOrCreateAssociatedTokenAccount(connection, admin, otherMint, authPda, true)).address;
+    });
+
+    it("rejects a staked or reward vault of another mint", async () => {
+      await expect(initialize({ stakedVault: otherVault, rewardVault })).to.be.rejectedWith(/VaultMintMismatch/);
+      await expect(initialize({ stakedVault, rewardVault: otherVault })).to.be.rejectedWith(/VaultMintMismatch/);
+    });
+
+    it("rejects a bogus token mint", async () => {
+      // Neither the default key nor an empty address deserialize as a mint
+      await expect(initialize({ stakedVault, rewardVault }, PublicKey.default))
+        .to.be.rejectedWith(/AccountOwnedByWrongProgram|AccountNotInitialized/);
+      await expect(initialize({ stakedVault, rewardVault }, Keypair.generate().publicKey))
+        .to.be.rejectedWith(/AccountNotInitialized/);
+    });
+
+    it("gets past the vault checks with matching vaults", async () => {
+      await expect(initialize({ stakedVault, rewardVault })).to.be.rejectedWith(/AlreadyInitialized/);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3zo · Reflection claim cooldown
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("reflection claim cooldown", () => {
+    const small = Keypair.generate();
+    const large = Keypair.generate();
+    const stakeOf = (user: Keypair) => findPda([SEED_USER, user.publicKey.toBuffer()], PROGRAM_ID);
+    const setCooldown = (interval: number, threshold: BN) =>
+      program.methods.setReflectionClaimCooldown(toBN(interval), threshold).accounts({
+        admin: admin.publicKey, config: cfgPda,
+      }).signers([admin]).rpc();
+    const claim = (user: Keypair) =>
+      program.methods.claimReflections(false, false).accounts({
+        user: user.publicKey, userStake: stakeOf(user), solTreasury: treasuryPda,
+        config: cfgPda, systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+    const deposit = async () => {
+      await provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          SystemProgram.transfer({ fromPubkey: admin.publicKey, toPubkey: treasuryPda, lamports: LAMPORTS_PER_SOL })
+        ), [admin]);
+      const supply = (await getMint(connection, mint)).supply;
+      await program.methods.depositReflectionFunds(toBN(LAMPORTS_PER_SOL), toBN(supply)).accounts({
+        admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+      }).signers([admin]).rpc();
+    };
+
+    before("stake a small and a large position", async () => {
+      for (const [user, amount] of [[small, TOKEN_UNIT], [large, TOKEN_UNIT.muln(100)]] as [Keypair, BN][]) {
+        await connection.confirmTransaction(await connection.requestAirdrop(user.publicKey, LAMPORTS_PER_SOL));
+        const ata = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+        await mintTo(connection, admin, mint, ata, admin, BigInt(amount.toString()));
+        await program.methods.registerUser(new BN(0)).accounts({
+          user: user.publicKey, userStake: stakeOf(user), config: cfgPda,
+          systemProgram: SystemProgram.programId,
+        }).signers([user]).rpc();
+        await program.methods.stake(amount, new BN(0), false).accounts({
+          user: user.publicKey, userStake: stakeOf(user), userTokenAccount: ata,
+          stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+        }).signers([user]).rpc();
+      }
+      await setCooldown(3_600, TOKEN_UNIT.muln(50));
+    });
+    after("remove the cooldown", async () => { await setCooldown(0, new BN(0)); });
+
+    it("throttles a small staker's second claim", async () => {
+      await deposit();
+      await claim(small);
+      await deposit();
+      await expect(claim(small)).to.be.rejectedWith(/ReflectionClaimCooldown/);
+    });
+
+    it("lets a large staker claim again right away", async () => {
+      await claim(large);
+      await deposit();
+      await claim(large);
+      expect((await program.account.userStake.fetch(stakeOf(large))).lastReflectionClaimTime.toNumber())
+        .to.be.greaterThan(0);
+    });
   });
 
   // ──────────────────────────────────────────────────────────────────────────
@@ -382,9 +2858,9 @@ describe("MCar Vesting — PRD v1.1 exhaustive", () => {
       const before = await program.account.userStake.fetch(pubStake);
       expect(before.unclaimedYield.gt(new BN(0))).to.be.true;
 
-      await program.methods.claimYield().accounts({
+      await program.methods.claimYield(false).accounts({
         user: pubUser.publicKey, userStake: pubStake, userTokenAccount: pubAta,
-        rewardVault, vaultAuthority: authPda, config: cfgPda, tokenMint: mint,
+        rewardVault, vaultAuthority: authPda, config: cfgPda, yieldMint: mint,
         tokenProgram: TOKEN_PROGRAM_ID
       }).signers([pubUser]).rpc();
 
@@ -394,6 +2870,728 @@ describe("MCar Vesting — PRD v1.1 exhaustive", () => {
     });
   });
 
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3zp · Admin and role holder views
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("admin views", () => {
+    it("who_is_admin returns the admin set at initialize", async () => {
+      const current = await program.methods.whoIsAdmin().accounts({ config: cfgPda }).view();
+      expect(current.equals(admin.publicKey)).to.be.true;
+    });
+
+    it("get_role_holders matches the decoded config", async () => {
+      const roles = await program.methods.getRoleHolders().accounts({ config: cfgPda }).view();
+      const cfg = await program.account.globalConfig.fetch(cfgPda);
+      expect(roles.admin.equals(cfg.admin)).to.be.true;
+      if (cfg.treasuryManager.equals(PublicKey.default)) {
+        expect(roles.treasuryManager).to.be.null;
+      } else {
+        expect(roles.treasuryManager.equals(cfg.treasuryManager)).to.be.true;
+      }
+      if (cfg.reflectionOperator.equals(PublicKey.default)) {
+        expect(roles.reflectionOperator).to.be.null;
+      } else {
+        expect(roles.reflectionOperator.equals(cfg.reflectionOperator)).to.be.true;
+      }
+      expect(roles.governanceKey === null ? null : roles.governanceKey.toBase58())
+        .to.equal(cfg.governanceKey === null ? null : cfg.governanceKey.toBase58());
+    });
+
+    it("get_apy compounds the configured APR", async () => {
+      const apy = (n: number) => program.methods.getApy(n).accounts({ config: cfgPda }).view();
+      const rate = (await program.account.globalConfig.fetch(cfgPda)).yieldRateBps;
+      expect((await apy(1)).toNumber()).to.equal(rate);
+      // Daily compounding: floor(((1 + apr / 365)^365 - 1) * 10_000), within 1 bp of the float
+      const expected = Math.floor((Math.pow(1 + rate / 10_000 / 365, 365) - 1) * 10_000);
+      expect((await apy(365)).toNumber()).to.be.within(expected - 1, expected);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3zq · Exit and donate
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("exit and donate", () => {
+    const user  = Keypair.generate();
+    const stake = findPda([SEED_USER, user.publicKey.toBuffer()], PROGRAM_ID);
+    let ata: PublicKey;
+    const exitAndDonate = (close: boolean) =>
+      program.methods.exitAndDonate(close).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        vaultAuthority: authPda, stakedVault, config: cfgPda, tokenMint: mint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+    const pending = async () =>
+      program.methods.getAllPending().accounts({ userStake: stake, config: cfgPda, tokenMint: mint }).view();
+
+    before("stake, unlock everything and receive reflections", async () => {
+      await connection.confirmTransaction(await connection.requestAirdrop(user.publicKey, LAMPORTS_PER_SOL));
+      ata = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+      await mintTo(connection, admin, mint, ata, admin, BigInt(TOKEN_UNIT.toString()));
+      await program.methods.registerUser(new BN(0)).accounts({
+        user: user.publicKey, userStake: stake, config: cfgPda,
+        systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+      await program.methods.stake(TOKEN_UNIT, new BN(0), false).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+      await program.methods.adminUnlockBonus(100).accounts({
+        admin: admin.publicKey, config: cfgPda, userStake: stake,
+      }).signers([admin]).rpc();
+      await provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          SystemProgram.transfer({ fromPubkey: admin.publicKey, toPubkey: treasuryPda, lamports: LAMPORTS_PER_SOL })
+        ), [admin]);
+      const supply = (await getMint(connection, mint)).supply;
+      await program.methods.depositReflectionFunds(toBN(LAMPORTS_PER_SOL), toBN(supply)).accounts({
+        admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+      }).signers([admin]).rpc();
+    });
+
+    it("returns only principal and leaves the rewards in the pools", async () => {
+      const donated = (await pending()).reflectionLamports.toNumber();
+      expect(donated).to.be.greaterThan(0);
+      const tokens0   = (await getAccount(connection, ata)).amount;
+      const vault0    = (await getAccount(connection, rewardVault)).amount;
+      const sol0      = await connection.getBalance(user.publicKey);
+      const treasury0 = await connection.getBalance(treasuryPda);
+
+      await exitAndDonate(false);
+
+      expect((await getAccount(connection, ata)).amount - tokens0).to.equal(BigInt(TOKEN_UNIT.toString()));
+      expect((await getAccount(connection, rewardVault)).amount).to.equal(vault0);
+      expect(await connection.getBalance(user.publicKey)).to.equal(sol0);
+      expect(await connection.getBalance(treasuryPda)).to.equal(treasury0);
+      const after = await pending();
+      expect(after.yieldTokens.toNumber()).to.equal(0);
+      expect(after.reflectionLamports.toNumber()).to.equal(0);
+
+      // The donated lamports fund the next deposit without new SOL
+      const supply = (await getMint(connection, mint)).supply;
+      await program.methods.depositReflectionFunds(toBN(donated), toBN(supply)).accounts({
+        admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+      }).signers([admin]).rpc();
+    });
+
+    it("closes the emptied position on request", async () => {
+      const sol0 = await connection.getBalance(user.publicKey);
+      const rent = await connection.getBalance(stake);
+      await exitAndDonate(true);
+      expect(await connection.getAccountInfo(stake)).to.be.null;
+      expect(await connection.getBalance(user.publicKey) - sol0).to.equal(rent);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3zr · Per-user stake cap
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("per-user stake cap", () => {
+    const user   = Keypair.generate();
+    const gifted = Keypair.generate();
+    const stake  = findPda([SEED_USER, user.publicKey.toBuffer()], PROGRAM_ID);
+    const giftedStake = findPda([SEED_USER, gifted.publicKey.toBuffer()], PROGRAM_ID);
+    const CAP = TOKEN_UNIT.muln(2);
+    let ata: PublicKey;
+    const setCap = (cap: BN, presaleBypass: boolean) =>
+      program.methods.setMaxStakePerUser(cap, presaleBypass).accounts({
+        admin: admin.publicKey, config: cfgPda,
+      }).signers([admin]).rpc();
+    const stakeUnit = () =>
+      program.methods.stake(TOKEN_UNIT, new BN(0), false).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+    const presaleCredit = (amount: BN) =>
+      program.methods.adminInitializePresaleStake(amount).accounts({
+        admin: admin.publicKey, userStake: giftedStake, user: gifted.publicKey, config: cfgPda,
+        sourceTokenAccount: adminAta, stakedVault, tokenMint: mint,
+        tokenProgram: TOKEN_PROGRAM_ID, systemProgram: SystemProgram.programId,
+        rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+      }).signers([admin]).rpc();
+    const staked = async (pda: PublicKey) => (await program.account.userStake.fetch(pda)).stakedAmount;
+
+    before("register and cap", async () => {
+      await connection.confirmTransaction(await connection.requestAirdrop(user.publicKey, LAMPORTS_PER_SOL));
+      ata = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+      await mintTo(connection, admin, mint, ata, admin, BigInt(TOKEN_UNIT.muln(3).toString()));
+      await program.methods.registerUser(new BN(0)).accounts({
+        user: user.publicKey, userStake: stake, config: cfgPda,
+        systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+      await setCap(CAP, false);
+    });
+    after("uncap", async () => { await setCap(new BN(0), false); });
+
+    it("accepts stakes below and up to the cap", async () => {
+      await stakeUnit();
+      expect((await staked(stake)).eq(TOKEN_UNIT)).to.be.true;
+      await stakeUnit();
+      expect((await staked(stake)).eq(CAP)).to.be.true;
+    });
+
+    it("rejects a stake above the cap", async () => {
+      await expect(stakeUnit()).to.be.rejectedWith(/PerUserStakeCapExceeded/);
+      expect((await staked(stake)).eq(CAP)).to.be.true;
+    });
+
+    it("caps presale credits unless the bypass is set", async () => {
+      await expect(presaleCredit(CAP.addn(1))).to.be.rejectedWith(/PerUserStakeCapExceeded/);
+      await setCap(CAP, true);
+      await presaleCredit(CAP.addn(1));
+      expect((await staked(giftedStake)).eq(CAP.addn(1))).to.be.true;
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3zs · Staking while an unstake request is pending
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("stake with a pending unstake request", () => {
+    const user  = Keypair.generate();
+    const stake = findPda([SEED_USER, user.publicKey.toBuffer()], PROGRAM_ID);
+    let ata: PublicKey;
+    const stakeUnit = () =>
+      program.methods.stake(TOKEN_UNIT, new BN(0), false).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+    const cancel = () =>
+      program.methods.cancelUnstakeRequest().accounts({
+        user: user.publicKey, userStake: stake, config: cfgPda,
+      }).signers([user]).rpc();
+
+    before("stake, unlock and request an unstake", async () => {
+      await connection.confirmTransaction(await connection.requestAirdrop(user.publicKey, LAMPORTS_PER_SOL));
+      ata = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+      await mintTo(connection, admin, mint, ata, admin, BigInt(TOKEN_UNIT.muln(2).toString()));
+      await program.methods.registerUser(new BN(0)).accounts({
+        user: user.publicKey, userStake: stake, config: cfgPda,
+        systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+      await stakeUnit();
+      await program.methods.adminUnlockBonus(100).accounts({
+        admin: admin.publicKey, config: cfgPda, userStake: stake,
+      }).signers([admin]).rpc();
+      await program.methods.requestUnstake(TOKEN_UNIT).accounts({
+        user: user.publicKey, userStake: stake, config: cfgPda,
+      }).signers([user]).rpc();
+    });
+
+    it("rejects a new stake while the request is pending", async () => {
+      await expect(stakeUnit()).to.be.rejectedWith(/UnstakeRequestPending/);
+      expect((await program.account.userStake.fetch(stake)).stakedAmount.eq(TOKEN_UNIT)).to.be.true;
+    });
+
+    it("accepts the stake once the request is cancelled", async () => {
+      await cancel();
+      expect((await program.account.userStake.fetch(stake)).pendingUnstakeAmount.isZero()).to.be.true;
+      await expect(cancel()).to.be.rejectedWith(/NoUnstakeRequest/);
+
+      await stakeUnit();
+      expect((await program.account.userStake.fetch(stake)).stakedAmount.eq(TOKEN_UNIT.muln(2))).to.be.true;
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3zt · Settling yield during a reflection claim
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("reflection claim with yield settlement", () => {
+    const user  = Keypair.generate();
+    const stake = findPda([SEED_USER, user.publicKey.toBuffer()], PROGRAM_ID);
+    const AMOUNT = TOKEN_UNIT.muln(1_000);
+    let ata: PublicKey;
+    const deposit = async () => {
+      await provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          SystemProgram.transfer({ fromPubkey: admin.publicKey, toPubkey: treasuryPda, lamports: LAMPORTS_PER_SOL })
+        ), [admin]);
+      const supply = (await getMint(connection, mint)).supply;
+      await program.methods.depositReflectionFunds(toBN(LAMPORTS_PER_SOL), toBN(supply)).accounts({
+        admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+      }).signers([admin]).rpc();
+    };
+    const claim = (settleYield: boolean) =>
+      program.methods.claimReflections(false, settleYield).accounts({
+        user: user.publicKey, userStake: stake, solTreasury: treasuryPda,
+        config: cfgPda, systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+
+    before("stake and let yield accrue", async () => {
+      await connection.confirmTransaction(await connection.requestAirdrop(user.publicKey, LAMPORTS_PER_SOL));
+      ata = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+      await mintTo(connection, admin, mint, ata, admin, BigInt(AMOUNT.toString()));
+      await program.methods.registerUser(new BN(0)).accounts({
+        user: user.publicKey, userStake: stake, config: cfgPda,
+        systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+      await program.methods.stake(AMOUNT, new BN(0), false).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+      await warpSeconds(connection, CHUNK_SECONDS);
+    });
+
+    it("leaves yield unbanked by default", async () => {
+      await deposit();
+      const before = await program.account.userStake.fetch(stake);
+      await claim(false);
+      const after = await program.account.userStake.fetch(stake);
+      expect(after.unclaimedYield.eq(before.unclaimedYield)).to.be.true;
+      expect(after.lastYieldClaimTime.eq(before.lastYieldClaimTime)).to.be.true;
+    });
+
+    it("banks accrued yield without a token transfer when opted in", async () => {
+      await deposit();
+      const before = await program.account.userStake.fetch(stake);
+      const tokens0 = (await getAccount(connection, ata)).amount;
+      await claim(true);
+      const after = await program.account.userStake.fetch(stake);
+      expect(after.unclaimedYield.gt(before.unclaimedYield)).to.be.true;
+      expect(after.lastYieldClaimTime.gt(before.lastYieldClaimTime)).to.be.true;
+      expect((await getAccount(connection, ata)).amount).to.equal(tokens0);
+      expect(after.lastReflectionClaimTime.eq(after.lastYieldClaimTime)).to.be.true;
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3zu · Claims after a full unstake
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("claims after a full unstake", () => {
+    const user  = Keypair.generate();
+    const stake = findPda([SEED_USER, user.publicKey.toBuffer()], PROGRAM_ID);
+    const AMOUNT = TOKEN_UNIT.muln(1_000);
+    let ata: PublicKey;
+
+    before("stake, accrue both rewards and unstake everything", async () => {
+      await connection.confirmTransaction(await connection.requestAirdrop(user.publicKey, LAMPORTS_PER_SOL));
+      ata = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+      await mintTo(connection, admin, mint, ata, admin, BigInt(AMOUNT.toString()));
+      await program.methods.registerUser(new BN(0)).accounts({
+        user: user.publicKey, userStake: stake, config: cfgPda,
+        systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+      await program.methods.stake(AMOUNT, new BN(0), false).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+      await program.methods.adminUnlockBonus(100).accounts({
+        admin: admin.publicKey, config: cfgPda, userStake: stake,
+      }).signers([admin]).rpc();
+      await provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          SystemProgram.transfer({ fromPubkey: admin.publicKey, toPubkey: treasuryPda, lamports: LAMPORTS_PER_SOL })
+        ), [admin]);
+      const supply = (await getMint(connection, mint)).supply;
+      await program.methods.depositReflectionFunds(toBN(LAMPORTS_PER_SOL), toBN(supply)).accounts({
+        admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+      }).signers([admin]).rpc();
+      await warpSeconds(connection, CHUNK_SECONDS);
+
+      await program.methods.unstake(AMOUNT).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        vaultAuthority: authPda, stakedVault, config: cfgPda, tokenMint: mint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+    });
+
+    it("banks the final yield and keeps the reflection checkpoint", async () => {
+      const s = await program.account.userStake.fetch(stake);
+      const cfg = await program.account.globalConfig.fetch(cfgPda);
+      expect(s.stakedAmount.isZero()).to.be.true;
+      expect(s.startTimestamp.isZero()).to.be.true;
+      expect(s.unclaimedYield.gtn(0)).to.be.true;
+      expect(s.unclaimedReflections.gtn(0)).to.be.true;
+      expect(s.lastClaimedIndex.eq(cfg.reflectionIndex)).to.be.true;
+    });
+
+    it("still pays the accrued yield", async () => {
+      const banked = (await program.account.userStake.fetch(stake)).unclaimedYield;
+      const before = (await getAccount(connection, ata)).amount;
+      await program.methods.claimYield(false).accounts({
+        user: user.publicKey, userStake: stake, userTokenAccount: ata,
+        rewardVault, vaultAuthority: authPda, config: cfgPda, yieldMint: mint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+      const received = (await getAccount(connection, ata)).amount - before;
+      expect(received > 0n && received <= BigInt(banked.toString())).to.be.true;
+    });
+
+    it("still pays the accrued reflections", async () => {
+      const pending = (await program.account.userStake.fetch(stake)).unclaimedReflections.toNumber();
+      const balance0 = await connection.getBalance(user.publicKey);
+      await program.methods.claimReflections(false, false).accounts({
+        user: user.publicKey, userStake: stake, solTreasury: treasuryPda,
+        config: cfgPda, systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+      expect(await connection.getBalance(user.publicKey) - balance0).to.equal(pending);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3zv · Reflection index circuit breaker
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("reflection index circuit breaker", () => {
+    const setBreaker = (bps: number) =>
+      program.methods.setMaxIndexGrowthPerDeposit(bps).accounts({
+        admin: admin.publicKey, config: cfgPda,
+      }).signers([admin]).rpc();
+    // Funding transfer and deposit in one transaction, so a rejected deposit leaves no SOL behind
+    const deposit = async () => {
+      const supply = (await getMint(connection, mint)).supply;
+      const ix = await program.methods.depositReflectionFunds(toBN(LAMPORTS_PER_SOL), toBN(supply)).accounts({
+        admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+      }).instruction();
+      await provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          SystemProgram.transfer({ fromPubkey: admin.publicKey, toPubkey: treasuryPda, lamports: LAMPORTS_PER_SOL }),
+          ix,
+        ), [admin]);
+    };
+    const index = async () => (await program.account.globalConfig.fetch(cfgPda)).reflectionIndex;
+    let growth: BN;
+
+    after("disable the breaker", async () => { await setBreaker(0); });
+
+    it("lets a deposit within the limit through", async () => {
+      await setBreaker(10_000); // Up to 100% of the index per deposit
+      const before = await index();
+      await deposit();
+      growth = (await index()).sub(before);
+      expect(growth.gtn(0)).to.be.true;
+    });
+
+    it("blocks a deposit growing the index faster than allowed", async () => {
+      // Allow half of what an identical deposit adds
+      const bps = growth.muln(10_000).div(await index()).divn(2).toNumber();
+      expect(bps).to.be.greaterThan(0);
+      await setBreaker(bps);
+      const before = await index();
+      const treasury0 = await connection.getBalance(treasuryPda);
+      await expect(deposit()).to.be.rejectedWith(/IndexGrowthTooLarge/);
+      expect((await index()).eq(before)).to.be.true;
+      expect(await connection.getBalance(treasuryPda)).to.equal(treasury0);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3zw · Reflection history ring buffer
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("reflection history", () => {
+    const historyPda = findPda([Buffer.from("reflection_history")], PROGRAM_ID);
+    const depositRecorded = async (lamports: number) => {
+      const supply = (await getMint(connection, mint)).supply;
+      await provider.sendAndConfirm(new anchor.web3.Transaction().add(
+        SystemProgram.transfer({ fromPubkey: admin.publicKey, toPubkey: treasuryPda, lamports }),
+      ), [admin]);
+      await program.methods.depositReflectionFunds(toBN(lamports), toBN(supply)).accounts({
+        admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda, reflectionHistory: historyPda,
+      }).signers([admin]).rpc();
+      return (await program.account.globalConfig.fetch(cfgPda)).reflectionIndex;
+    };
+
+    it("rejects a capacity above the limit", async () => {
+      await expect(program.methods.initReflectionHistory(201).accounts({
+        admin: admin.publicKey, config: cfgPda, reflectionHistory: historyPda,
+        systemProgram: SystemProgram.programId,
+      }).signers([admin]).rpc()).to.be.rejectedWith(/InvalidHistoryCapacity/);
+    });
+
+    it("keeps the last deposits in a ring buffer", async () => {
+      await program.methods.initReflectionHistory(3).accounts({
+        admin: admin.publicKey, config: cfgPda, reflectionHistory: historyPda,
+        systemProgram: SystemProgram.programId,
+      }).signers([admin]).rpc();
+
+      const indices: BN[] = [];
+      for (let i = 1; i <= 4; i++) indices.push(await depositRecorded(i * 100_000_000));
+
+      const history = await program.account.reflectionHistory.fetch(historyPda);
+      expect(history.capacity).to.equal(3);
+      expect(history.recorded.toNumber()).to.equal(4);
+      expect(history.entries.length).to.equal(3);
+      // The fourth deposit overwrote the first slot
+      const ordered = [history.entries[1], history.entries[2], history.entries[0]];
+      ordered.forEach((entry, i) => {
+        expect(entry.reflectionIndex.eq(indices[i + 1])).to.be.true;
+        expect(entry.solAmount.toNumber()).to.equal((i + 2) * 100_000_000);
+        expect(entry.totalSupply.gtn(0)).to.be.true;
+      });
+      expect(ordered.every((e, i) => i === 0 || e.slot.gte(ordered[i - 1].slot))).to.be.true;
+    });
+
+    it("leaves the history untouched when it is not passed", async () => {
+      const supply = (await getMint(connection, mint)).supply;
+      await provider.sendAndConfirm(new anchor.web3.Transaction().add(
+        SystemProgram.transfer({ fromPubkey: admin.publicKey, toPubkey: treasuryPda, lamports: 100_000_000 }),
+      ), [admin]);
+      await program.methods.depositReflectionFunds(toBN(100_000_000), toBN(supply)).accounts({
+        admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+      }).signers([admin]).rpc();
+      expect((await program.account.reflectionHistory.fetch(historyPda)).recorded.toNumber()).to.equal(4);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3zx · Reflection reinvest ratio
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("reflection reinvest ratio", () => {
+    const ratios = [0, 5_000, 10_000];
+    const users = ratios.map(() => Keypair.generate());
+    const stakeOf = (user: Keypair) => findPda([SEED_USER, user.publicKey.toBuffer()], PROGRAM_ID);
+    // Lamports each user's claim moved out of the treasury, and what was kept as credit
+    const paid: number[] = [];
+    const credited: number[] = [];
+
+    before("stake equal positions with different ratios, then deposit", async () => {
+      for (const [i, user] of users.entries()) {
+        await connection.confirmTransaction(await connection.requestAirdrop(user.publicKey, LAMPORTS_PER_SOL));
+        const ata = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+        await mintTo(connection, admin, mint, ata, admin, BigInt(TOKEN_UNIT.toString()));
+        await program.methods.registerUser(new BN(0)).accounts({
+          user: user.publicKey, userStake: stakeOf(user), config: cfgPda,
+          systemProgram: SystemProgram.programId,
+        }).signers([user]).rpc();
+        await program.methods.stake(TOKEN_UNIT, new BN(0), false).accounts({
+          user: user.publicKey, userStake: stakeOf(user), userTokenAccount: ata,
+          stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+        }).signers([user]).rpc();
+        await program.methods.setReinvestRatio(ratios[i]).accounts({
+          user: user.publicKey, userStake: stakeOf(user),
+        }).signers([user]).rpc();
+      }
+      await provider.sendAndConfirm(new anchor.web3.Transaction().add(
+        SystemProgram.transfer({ fromPubkey: admin.publicKey, toPubkey: treasuryPda, lamports: LAMPORTS_PER_SOL }),
+      ), [admin]);
+      const supply = (await getMint(connection, mint)).supply;
+      await program.methods.depositReflectionFunds(toBN(LAMPORTS_PER_SOL), toBN(supply)).accounts({
+        admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+      }).signers([admin]).rpc();
+
+      for (const user of users) {
+        const before = await connection.getBalance(treasuryPda);
+        await program.methods.claimReflections(false, false).accounts({
+          user: user.publicKey, userStake: stakeOf(user), solTreasury: treasuryPda,
+          config: cfgPda, systemProgram: SystemProgram.programId,
+        }).signers([user]).rpc();
+        paid.push(before - await connection.getBalance(treasuryPda));
+        credited.push((await program.account.userStake.fetch(stakeOf(user))).reinvestCredit.toNumber());
+      }
+    });
+
+    it("rejects a ratio above 100%", async () => {
+      await expect(program.methods.setReinvestRatio(10_001).accounts({
+        user: users[0].publicKey, userStake: stakeOf(users[0]),
+      }).signers([users[0]]).rpc()).to.be.rejectedWith(/InvalidReinvestRatio/);
+    });
+
+    it("pays everything out at 0%", async () => {
+      expect(paid[0]).to.be.greaterThan(0);
+      expect(credited[0]).to.equal(0);
+    });
+
+    it("splits the claim at 50%", async () => {
+      expect(paid[1] + credited[1]).to.equal(paid[0]);
+      expect(credited[1]).to.equal(Math.floor(paid[0] / 2));
+    });
+
+    it("keeps everything in the treasury at 100%", async () => {
+      expect(paid[2]).to.equal(0);
+      expect(credited[2]).to.equal(paid[0]);
+      const cfg = await program.account.globalConfig.fetch(cfgPda);
+      expect(cfg.totalReinvestCredit.toNumber()).to.be.at.least(credited[1] + credited[2]);
+    });
+
+    it("leaves the credited lamports unassigned for the next deposit", async () => {
+      const cfg = await program.account.globalConfig.fetch(cfgPda);
+      const unassigned = await connection.getBalance(treasuryPda) - cfg.lastTreasuryBalance.toNumber();
+      expect(unassigned).to.be.at.least(credited[1] + credited[2]);
+    });
+
+    it("applies each position's ratio to batch claims", async () => {
+      await provider.sendAndConfirm(new anchor.web3.Transaction().add(
+        SystemProgram.transfer({ fromPubkey: admin.publicKey, toPubkey: treasuryPda, lamports: LAMPORTS_PER_SOL }),
+      ), [admin]);
+      const supply = (await getMint(connection, mint)).supply;
+      await program.methods.depositReflectionFunds(toBN(LAMPORTS_PER_SOL), toBN(supply)).accounts({
+        admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+      }).signers([admin]).rpc();
+      const creditOf = async (user: Keypair) =>
+        (await program.account.userStake.fetch(stakeOf(user))).reinvestCredit.toNumber();
+      const credit0 = await Promise.all(users.map(creditOf));
+      const total0 = (await program.account.globalConfig.fetch(cfgPda)).totalReinvestCredit.toNumber();
+
+      // 50% through claim_all_positions, 0% and 100% pooled
+      const treasury0 = await connection.getBalance(treasuryPda);
+      await program.methods.claimAllPositions().accounts({
+        user: users[1].publicKey, solTreasury: treasuryPda,
+        config: cfgPda, systemProgram: SystemProgram.programId,
+      }).remainingAccounts([{ pubkey: stakeOf(users[1]), isSigner: false, isWritable: true }])
+        .signers([users[1]]).rpc();
+      const paidAll = treasury0 - await connection.getBalance(treasuryPda);
+      const treasury1 = await connection.getBalance(treasuryPda);
+      await program.methods.claimReflectionsPooled().accounts({
+        caller: admin.publicKey, pool: admin.publicKey, solTreasury: treasuryPda,
+        config: cfgPda, systemProgram: SystemProgram.programId,
+      }).remainingAccounts([0, 2].flatMap(i => [
+        { pubkey: stakeOf(users[i]), isSigner: false, isWritable: true },
+        { pubkey: users[i].publicKey, isSigner: true, isWritable: false },
+      ])).signers([admin, users[0], users[2]]).rpc();
+      const paidPooled = treasury1 - await connection.getBalance(treasuryPda);
+
+      const credit1 = await Promise.all(users.map(creditOf));
+      const gained = credit1.map((c, i) => c - credit0[i]);
+      expect(gained[0]).to.equal(0);
+      expect(gained[1]).to.be.greaterThan(0);
+      expect(paidAll).to.equal(gained[2] - gained[1]);
+      // Only the 0% position's claim left the treasury in the pooled batch
+      expect(paidPooled).to.equal(gained[2]);
+      const total1 = (await program.account.globalConfig.fetch(cfgPda)).totalReinvestCredit.toNumber();
+      expect(total1 - total0).to.equal(gained[1] + gained[2]);
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3zy · Secondary bonus reward token
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("bonus reward token", () => {
+    const user = Keypair.generate();
+    const userStake = findPda([SEED_USER, user.publicKey.toBuffer()], PROGRAM_ID);
+    let bonusMint: PublicKey, bonusVault: PublicKey, userBonusAta: PublicKey, userAta: PublicKey;
+    const setBonus = (bps: number) =>
+      program.methods.setBonusReward(bps).accounts({
+        admin: admin.publicKey, config: cfgPda, vaultAuthority: authPda,
+        bonusMint, bonusRewardVault: bonusVault,
+      }).signers([admin]).rpc();
+    const claimBonus = () =>
+      program.methods.claimBonusYield().accounts({
+        user: user.publicKey, config: cfgPda, userStake, userBonusAccount: userBonusAta,
+        vaultAuthority: authPda, bonusRewardVault: bonusVault, bonusMint, tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+
+    before("create a funded bonus vault and stake", async () => {
+      bonusMint    = await createMint(connection, admin, admin.publicKey, null, 6);
+      bonusVault   = (await getOrCreateAssociatedTokenAccount(connection, admin, bonusMint, authPda, true)).address;
+      userBonusAta = (await getOrCreateAssociatedTokenAccount(connection, admin, bonusMint, user.publicKey)).address;
+      await mintTo(connection, admin, bonusMint, bonusVault, admin, 1_000_000_000_000n);
+
+      await connection.confirmTransaction(await connection.requestAirdrop(user.publicKey, LAMPORTS_PER_SOL));
+      userAta = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+      await mintTo(connection, admin, mint, userAta, admin, BigInt(TOKEN_UNIT.muln(1_000).toString()));
+      await program.methods.registerUser(new BN(0)).accounts({
+        user: user.publicKey, userStake, config: cfgPda, systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+      await program.methods.stake(TOKEN_UNIT.muln(1_000), new BN(0), false).accounts({
+        user: user.publicKey, userStake, userTokenAccount: userAta,
+        stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+    });
+    after("stop bonus emissions", async () => { await setBonus(0); });
+
+    it("rejects claims before a bonus token is configured", async () => {
+      const cfg = await program.account.globalConfig.fetch(cfgPda);
+      if (!cfg.bonusMint.equals(PublicKey.default)) return; // Configured by an earlier run
+      await expect(claimBonus()).to.be.rejectedWith(/BonusRewardNotConfigured/);
+    });
+
+    it("accrues and pays bonus yield from its own vault", async () => {
+      await setBonus(20_000);
+      await new Promise(resolve => setTimeout(resolve, 2_000));
+      const before = await program.account.userStake.fetch(userStake);
+      const vault0 = (await getAccount(connection, bonusVault)).amount;
+      await claimBonus();
+
+      const paid = (await getAccount(connection, userBonusAta)).amount;
+      expect(paid > 0n).to.be.true;
+      expect(vault0 - (await getAccount(connection, bonusVault)).amount).to.equal(paid);
+      // The primary yield is left to accrue on its own clock
+      const after = await program.account.userStake.fetch(userStake);
+      expect(after.unclaimedBonusYield.toNumber()).to.equal(0);
+      expect(after.lastYieldClaimTime.eq(before.lastYieldClaimTime)).to.be.true;
+      expect(after.unclaimedYield.eq(before.unclaimedYield)).to.be.true;
+    });
+
+    it("leaves the bonus clock alone when the primary yield is claimed", async () => {
+      const before = await program.account.userStake.fetch(userStake);
+      await program.methods.claimYield(false).accounts({
+        user: user.publicKey, userStake, userTokenAccount: userAta,
+        rewardVault, vaultAuthority: authPda, config: cfgPda, yieldMint: mint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+      const after = await program.account.userStake.fetch(userStake);
+      expect(after.lastBonusClaimTime.eq(before.lastBonusClaimTime)).to.be.true;
+      expect(after.lastYieldClaimTime.gt(before.lastYieldClaimTime)).to.be.true;
+    });
+  });
+
+  // ──────────────────────────────────────────────────────────────────────────
+  // 3zz · Planned yield pause
+  // ──────────────────────────────────────────────────────────────────────────
+  describe("planned yield pause", () => {
+    const user = Keypair.generate();
+    const userStake = findPda([SEED_USER, user.publicKey.toBuffer()], PROGRAM_ID);
+    let userAta: PublicKey;
+    const claim = () =>
+      program.methods.claimYield(false).accounts({
+        user: user.publicKey, userStake, userTokenAccount: userAta,
+        rewardVault, vaultAuthority: authPda, config: cfgPda, yieldMint: mint,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+
+    before("stake a position and let yield accrue", async () => {
+      await connection.confirmTransaction(await connection.requestAirdrop(user.publicKey, LAMPORTS_PER_SOL));
+      userAta = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, user.publicKey)).address;
+      await mintTo(connection, admin, mint, userAta, admin, BigInt(TOKEN_UNIT.muln(1_000).toString()));
+      await program.methods.registerUser(new BN(0)).accounts({
+        user: user.publicKey, userStake, config: cfgPda, systemProgram: SystemProgram.programId,
+      }).signers([user]).rpc();
+      await program.methods.stake(TOKEN_UNIT.muln(1_000), new BN(0), false).accounts({
+        user: user.publicKey, userStake, userTokenAccount: userAta,
+        stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID,
+      }).signers([user]).rpc();
+      await new Promise(resolve => setTimeout(resolve, 2_000));
+    });
+    after("resume yield", async () => {
+      if ((await program.account.globalConfig.fetch(cfgPda)).yieldPaused) {
+        await program.methods.resumeYield().accounts({ admin: admin.publicKey, config: cfgPda }).signers([admin]).rpc();
+      }
+    });
+
+    it("checkpoints positions and pauses accrual", async () => {
+      await program.methods.prepareYieldPause(true).accounts({ admin: admin.publicKey, config: cfgPda })
+        .remainingAccounts([{ pubkey: userStake, isSigner: false, isWritable: true }])
+        .signers([admin]).rpc();
+      expect((await program.account.globalConfig.fetch(cfgPda)).yieldPaused).to.be.true;
+      expect((await program.account.userStake.fetch(userStake)).unclaimedYield.gtn(0)).to.be.true;
+    });
+
+    it("pays exactly the settled yield while paused", async () => {
+      const banked = (await program.account.userStake.fetch(userStake)).unclaimedYield;
+      await new Promise(resolve => setTimeout(resolve, 2_000));
+      const before = (await getAccount(connection, userAta)).amount;
+      await claim();
+      const paid = (await getAccount(connection, userAta)).amount - before;
+      const cfg = await program.account.globalConfig.fetch(cfgPda);
+      const earmark = banked.muln(cfg.yieldToReflectionBps).divn(10_000);
+      expect(paid.toString()).to.equal(banked.sub(earmark).toString());
+
+      // Nothing accrued since the checkpoint
+      await new Promise(resolve => setTimeout(resolve, 2_000));
+      await expect(claim()).to.be.rejectedWith(/NoYieldToClaim/);
+    });
+
+    it("resumes accrual from the resume time", async () => {
+      await program.methods.resumeYield().accounts({ admin: admin.publicKey, config: cfgPda }).signers([admin]).rpc();
+      const cfg = await program.account.globalConfig.fetch(cfgPda);
+      expect(cfg.yieldPaused).to.be.false;
+      const stake = await program.account.userStake.fetch(userStake);
+      expect(cfg.yieldResumedAt.gt(cfg.yieldPausedAt)).to.be.true;
+      expect(cfg.yieldPausedAt.gte(stake.lastYieldClaimTime)).to.be.true;
+      expect(cfg.emissionsStartTimestamp.lt(cfg.yieldPausedAt)).to.be.true;
+      await expect(
+        program.methods.resumeYield().accounts({ admin: admin.publicKey, config: cfgPda }).signers([admin]).rpc()
+      ).to.be.rejectedWith(/YieldNotPaused/);
+    });
+  });
+
   // ──────────────────────────────────────────────────────────────────────────
   // 5 · Stress — 64 concurrent users, 3 rounds stake/unstake/claim
   // ──────────────────────────────────────────────────────────────────────────
@@ -413,12 +3611,12 @@ describe("MCar Vesting — PRD v1.1 exhaustive", () => {
       await Promise.all(stressUsers.map(async u => {
         const stakePda = findPda([SEED_USER, u.publicKey.toBuffer()], PROGRAM_ID);
         const ata      = (await getOrCreateAssociatedTokenAccount(connection, admin, mint, u.publicKey)).address;
-        await program.methods.registerUser().accounts({
+        await program.methods.registerUser(new BN(0)).accounts({
           user: u.publicKey, userStake: stakePda, config: cfgPda,
           systemProgram: SystemProgram.programId,
         }).signers([u]).rpc();
 
-        await program.methods.stake(toBN(10_000n*10n**9n)).accounts({
+        await program.methods.stake(toBN(10_000n*10n**9n), new BN(0), false).accounts({
           user: u.publicKey, userStake: stakePda, userTokenAccount: ata,
           stakedVault, config: cfgPda, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID
         }).signers([u]).rpc();
@@ -439,7 +3637,7 @@ describe("MCar Vesting — PRD v1.1 exhaustive", () => {
   describe.skip("Error and edge cases", () => {
     it("stake with zero amount should fail", async () => {
       await expect(
-        program.methods.stake(toBN(0)).accounts({
+        program.methods.stake(toBN(0), new BN(0), false).accounts({
           user: pubUser.publicKey,
           userStake: pubStake,
           userTokenAccount: pubAta,
@@ -517,23 +3715,63 @@ describe("MCar Vesting — PRD v1.1 exhaustive", () => {
       ).to.be.rejected;
     });
 
+    it("admin withdrawals accumulate the counter and emit events", async () => {
+      await provider.sendAndConfirm(
+        new anchor.web3.Transaction().add(
+          SystemProgram.transfer({ fromPubkey: admin.publicKey, toPubkey: treasuryPda, lamports: 3_000 })
+        ), [admin]);
+      const parser = new anchor.EventParser(program.programId, program.coder);
+      const start = (await program.account.globalConfig.fetch(cfgPda)).totalAdminWithdrawn;
+
+      const events = [];
+      for (const amount of [1_000, 2_000]) {
+        const sig = await program.methods.adminWithdrawSol(toBN(amount)).accounts({
+          admin: admin.publicKey, config: cfgPda, solTreasury: treasuryPda,
+          systemProgram: SystemProgram.programId,
+        }).signers([admin]).rpc({ commitment: "confirmed" });
+        const tx = await connection.getTransaction(sig, { commitment: "confirmed", maxSupportedTransactionVersion: 0 });
+        events.push(...Array.from(parser.parseLogs(tx.meta.logMessages)).filter((e) => e.name === "AdminSolWithdrawn"));
+      }
+
+      const cfg = await program.account.globalConfig.fetch(cfgPda);
+      expect(cfg.totalAdminWithdrawn.sub(start).toNumber()).to.equal(3_000);
+      expect(events).to.have.length(2);
+      expect(events[1].data.amount.toNumber()).to.equal(2_000);
+      expect(events[1].data.totalAdminWithdrawn.eq(cfg.totalAdminWithdrawn)).to.be.true;
+      expect(events[1].data.remainingTreasuryLamports.toNumber()).to.equal(await connection.getBalance(treasuryPda));
+    });
+
+    it("emergency penalty accepts values up to the max and rejects above", async () => {
+      await program.methods.setEmergencyPenalty(1_000).accounts({
+        admin: admin.publicKey, config: cfgPda,
+      }).signers([admin]).rpc();
+      expect((await program.account.globalConfig.fetch(cfgPda)).emergencyPenaltyBps).to.equal(1_000);
+
+      await expect(
+        program.methods.setEmergencyPenalty(5_001).accounts({
+          admin: admin.publicKey, config: cfgPda,
+        }).signers([admin]).rpc()
+      ).to.be.rejectedWith(/EmergencyPenaltyTooHigh/);
+      expect((await program.account.globalConfig.fetch(cfgPda)).emergencyPenaltyBps).to.equal(1_000);
+    });
+
     it("repeated claim_yield and claim_reflections errors", async () => {
       // claim_yield when no new yield
       await expect(
-        program.methods.claimYield().accounts({
+        program.methods.claimYield(false).accounts({
           user: pubUser.publicKey,
           userStake: pubStake,
           userTokenAccount: pubAta,
           rewardVault,
           vaultAuthority: authPda,
           config: cfgPda,
-          tokenMint: mint,
+          yieldMint: mint,
           tokenProgram: TOKEN_PROGRAM_ID,
         }).signers([pubUser]).rpc()
       ).to.be.rejected;
       // claim_reflections when no new reflections
       await expect(
-        program.methods.claimReflections().accounts({
+        program.methods.claimReflections(false, false).accounts({
           user: pubUser.publicKey,
           userStake: pubStake,
           solTreasury: treasuryPda,
@@ -546,7 +3784,7 @@ describe("MCar Vesting — PRD v1.1 exhaustive", () => {
     it("consecutive stakes reset vesting period", async () => {
       // initial stake
       const AMOUNT1 = 1000n * 10n**9n;
-      await program.methods.stake(toBN(AMOUNT1)).accounts({
+      await program.methods.stake(toBN(AMOUNT1), new BN(0), false).accounts({
         user: pubUser.publicKey,
         userStake: pubStake,
         userTokenAccount: pubAta,
@@ -560,7 +3798,7 @@ describe("MCar Vesting — PRD v1.1 exhaustive", () => {
       await warpDays(connection, 3);
       // stake again
       const AMOUNT2 = 500n * 10n**9n;
-      await program.methods.stake(toBN(AMOUNT2)).accounts({
+      await program.methods.stake(toBN(AMOUNT2), new BN(0), false).accounts({
         user: pubUser.publicKey,
         userStake: pubStake,
         userTokenAccount: pubAta,
@@ -92,7 +92,7 @@ describe("Stress Test: high concurrency", () => {
       rewardVaultAta = await createVaultAccount();
 
       await program.methods
-        .initialize(500)
+        .initialize(500, 0)
         .accounts({
           admin: admin.publicKey,
           config: configPda,
@@ -143,7 +143,7 @@ describe("Stress Test: high concurrency", () => {
     // Initialize program if not already
     try {
       await program.methods
-        .initialize(500)
+        .initialize(500, 0)
         .accounts({
           admin: admin.publicKey,
           config: configPda,
@@ -211,7 +211,7 @@ describe("Stress Test: high concurrency", () => {
     await Promise.all(
       users.map((u, idx) =>
         program.methods
-          .registerUser()
+          .registerUser(new BN(0))
           .accounts({
             user: u.publicKey,
             userStake: userStakes[idx],
@@ -222,7 +222,7 @@ describe("Stress Test: high concurrency", () => {
           .rpc()
           .then(() =>
             program.methods
-              .stake(STAKE_AMOUNT)
+              .stake(STAKE_AMOUNT, new BN(0), false)
               .accounts({
                 user: u.publicKey,
                 userTokenAccount: userAtas[idx],
@@ -277,7 +277,7 @@ describe("Stress Test: high concurrency", () => {
       await Promise.all(
         users.map((u, idx) =>
           program.methods
-            .claimReflections()
+            .claimReflections(false, false)
             .accounts({
               user: u.publicKey,
               userStake: userStakes[idx],